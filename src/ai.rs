@@ -0,0 +1,329 @@
+use crate::logic;
+use crate::game::Game;
+use crate::fen;
+use crate::search::{ self, Budget, };
+use crate::takeback;
+
+use std::sync::mpsc::{ self, Receiver, };
+use std::thread;
+use std::time::Duration;
+
+/// Plays against the built-in searcher
+pub struct Ai {
+
+    game: Game,
+    state: logic::State,
+    player: logic::Player,
+    budget: Budget,
+    // Set while the searcher is thinking
+    search: Option<Receiver<Option<logic::Move>>>,
+    history: Vec<String>,
+    // The game before each move, for taking them back
+    undo: Vec<Game>,
+}
+
+impl Ai {
+
+    pub fn new(budget: Budget) -> logic::Layer {
+
+        Box::new(Self {
+            game: Game::new(),
+            state: logic::State::SelectPiece,
+            player: logic::Player::White,
+            budget,
+            search: None,
+            history: Vec::new(),
+            undo: Vec::new(),
+        })
+    }
+
+    fn make_move(
+        &mut self,
+        from: logic::Square,
+        dst: logic::Square,
+        promotion: Option<logic::Piece>,
+    ) {
+
+        let mov = logic::Move { from, to: dst, promotion, };
+        let before = self.game.clone();
+        match self.game.play(mov) {
+            Some(san) => {
+                self.history.push(san);
+                self.undo.push(before);
+            },
+            None => {
+                self.state = logic::State::SelectPiece;
+                return;
+            },
+        }
+
+        if let Some(state) = self.game.outcome() {
+            self.state = state;
+            return;
+        }
+
+        // Search on another thread so the GUI keeps drawing
+        let game = self.game.clone();
+        let player = self.player.other();
+        let budget = self.budget;
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = sender.send(search::best_move(&game, player, budget));
+        });
+
+        self.search = Some(receiver);
+        self.state = logic::State::OpponentTurn;
+    }
+
+    fn ai_move(&mut self, mov: Option<logic::Move>) {
+
+        let mov = match mov {
+            Some(mov) => mov,
+            None => {
+                println!("AI has no moves left");
+                self.state = logic::State::CheckMate(self.player);
+                return;
+            },
+        };
+
+        let before = self.game.clone();
+        match self.game.play(mov) {
+            Some(san) => {
+                self.history.push(san);
+                self.undo.push(before);
+            },
+            None => {
+                println!("AI picked a move the backend refuses, from {} to {}", mov.from, mov.to);
+                self.state = logic::State::Disconnected { reason: logic::Disconnect::Invalid, };
+                return;
+            },
+        }
+
+        self.state = self.game.outcome().unwrap_or(logic::State::SelectPiece);
+    }
+}
+
+impl logic::Interface for Ai {
+
+    fn get_state(&self) -> logic::State {
+
+        self.state
+    }
+
+    fn get_player(&self) -> Option<logic::Player> {
+
+        Some(self.player)
+    }
+
+    fn update(&mut self) {
+
+        let result = match &self.search {
+            Some(receiver) => receiver.try_recv(),
+            None => return,
+        };
+
+        match result {
+            Ok(mov) => {
+                self.search = None;
+                self.ai_move(mov);
+            },
+            Err(mpsc::TryRecvError::Empty) => (),
+            Err(mpsc::TryRecvError::Disconnected) => {
+                println!("AI search thread died");
+                self.search = None;
+                self.state = logic::State::Disconnected { reason: logic::Disconnect::Invalid, };
+            },
+        }
+    }
+
+    fn get_piece_at(&self, at: logic::Square) -> Option<(logic::Piece, logic::Player)> {
+
+        self.game.piece_at(at)
+    }
+
+    fn get_destinations(&self, from: logic::Square) -> Vec<logic::Square> {
+
+        self.game.destinations(from)
+    }
+
+    fn select_piece(&mut self, at: logic::Square) {
+
+        if !matches!(self.state, logic::State::SelectPiece) {
+            return;
+        }
+
+        // Must be our piece
+        match self.game.piece_at(at) {
+            Some((_, player)) if player == self.player => (),
+            _ => return,
+        }
+
+        self.state = logic::State::SelectMove { from: at, };
+    }
+
+    fn play_move(&mut self, dst: logic::Square) {
+
+        let from = match self.state {
+            logic::State::SelectMove { from } => from,
+            _ => return,
+        };
+
+        // Pawns reaching the last rank need a promotion first
+        if self.game.promotes(from, dst) {
+            self.state = logic::State::SelectPromotion { from, at: dst, };
+            return;
+        }
+
+        self.make_move(from, dst, None);
+    }
+
+    fn select_promotion(&mut self, piece: logic::Piece) {
+
+        let (from, at) = match self.state {
+            logic::State::SelectPromotion { from, at } => (from, at),
+            _ => return,
+        };
+
+        self.make_move(from, at, Some(piece));
+    }
+
+    fn cancel_promotion(&mut self) {
+
+        if let logic::State::SelectPromotion { from, .. } = self.state {
+            self.state = logic::State::SelectMove { from, };
+        }
+    }
+
+    fn get_history(&self) -> &[String] {
+
+        &self.history
+    }
+
+    fn get_fen(&self) -> String {
+
+        self.game.to_fen()
+    }
+
+    fn get_start_position(&self) -> Option<fen::Position> {
+
+        Some(self.game.start_position())
+    }
+
+    fn get_clock(&self, _player: logic::Player) -> Option<Duration> {
+
+        None
+    }
+
+    fn resign(&mut self) {
+
+        if self.state.is_playing() {
+            // The searcher's answer is no longer needed
+            self.search = None;
+            self.state = logic::State::Resigned { winner: self.player.other(), };
+        }
+    }
+
+    /// The AI answers at once, so no offer is ever left standing
+    fn offer_draw(&mut self) {
+
+        if !self.state.is_playing() {
+            return;
+        }
+
+        if search::accepts_draw(&self.game, self.player.other()) {
+            println!("AI accepts the draw");
+            self.search = None;
+            self.state = logic::State::Drawn { reason: logic::DrawReason::Agreement, };
+        } else {
+            println!("AI declines the draw");
+        }
+    }
+
+    /// Takes back our last move and the AI's answer to it, even after
+    /// the game ended
+    fn request_takeback(&mut self) {
+
+        if !self.state.is_playing() && !self.state.is_over() {
+            return;
+        }
+
+        let plies = takeback::plies(self.player, self.game.to_move());
+        if plies > self.undo.len() {
+            return;
+        }
+
+        // The searcher's answer is for a position that is gone
+        self.search = None;
+        let at = self.undo.len() - plies;
+        self.game = self.undo[at].clone();
+        self.undo.truncate(at);
+        self.history.truncate(at);
+        self.state = logic::State::SelectPiece;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    use std::time::Instant;
+
+    // Shallow so the answers come quickly
+    const BUDGET: Budget = Budget { depth: 1, time: None, };
+
+    fn square(s: &str) -> logic::Square {
+
+        logic::Square::from_algebraic(s).unwrap()
+    }
+
+    fn play(ai: &mut logic::Layer, from: &str, to: &str) {
+
+        ai.select_piece(square(from));
+        ai.play_move(square(to));
+    }
+
+    fn wait_for_answer(ai: &mut logic::Layer) {
+
+        let start = Instant::now();
+        while ai.get_state() == logic::State::OpponentTurn {
+            assert!(start.elapsed() < Duration::from_secs(10), "AI never answered");
+            ai.update();
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn answers_our_moves() {
+
+        let mut ai = Ai::new(BUDGET);
+        play(&mut ai, "e2", "e4");
+        assert!(matches!(ai.get_state(), logic::State::OpponentTurn));
+
+        wait_for_answer(&mut ai);
+        assert!(matches!(ai.get_state(), logic::State::SelectPiece));
+        assert_eq!(ai.get_history().len(), 2);
+    }
+
+    #[test]
+    fn accepts_a_draw_when_not_ahead() {
+
+        let mut ai = Ai::new(BUDGET);
+        ai.offer_draw();
+        assert!(matches!(ai.get_state(), logic::State::Drawn { reason: logic::DrawReason::Agreement, }));
+    }
+
+    #[test]
+    fn takes_back_its_answer_too() {
+
+        let mut ai = Ai::new(BUDGET);
+        let start = ai.get_fen();
+        play(&mut ai, "e2", "e4");
+        wait_for_answer(&mut ai);
+
+        ai.request_takeback();
+        assert!(matches!(ai.get_state(), logic::State::SelectPiece));
+        assert!(ai.get_history().is_empty());
+        assert_eq!(ai.get_fen(), start);
+    }
+}
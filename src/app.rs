@@ -1,9 +1,15 @@
 
 use crate::logic;
+use crate::fen;
+use crate::pgn;
+use chess_network_protocol as protocol;
 use ggez::*;
 use ggez::graphics::*;
 use ggez::input::*;
 use glam::Vec2;
+use std::env;
+use std::path::PathBuf;
+use std::time::{ Duration, Instant, };
 
 // The pixel offset of the first square in the board texture
 const BOARD_OFFSET: u32 = 40;
@@ -11,6 +17,17 @@ const BOARD_OFFSET: u32 = 40;
 const SQUARE_OFFSET: u32 = 22;
 // The width/height of the board texture
 const BOARD_SIZE: u32 = 256;
+// How long a refused destination stays marked
+const FLASH_TIME: Duration = Duration::from_millis(400);
+// Width of the move history panel, relative to the board
+const PANEL_WIDTH: f32 = 0.4;
+// Lines that fit in the panel, the first and last show the clocks
+const PANEL_LINES: usize = 24;
+// Clocks turn red with less time than this left
+const LOW_TIME: Duration = Duration::from_secs(20);
+const HISTORY_LINES: usize = PANEL_LINES - 2;
+// How long a first press of the resign key waits for the second
+const RESIGN_CONFIRM_TIME: Duration = Duration::from_secs(3);
 
 struct Images {
     pawn:   Image,
@@ -27,11 +44,32 @@ struct Gui {
     board: Image,
     black: Images,
     white: Images,
+    // Illegal destination that was clicked, and when
+    flash: Option<(logic::Square, Instant)>,
+    orientation: Orientation,
+    // History lines scrolled back from the latest move
+    scroll: usize,
+    // Where games are saved as PGN
+    pgn_dir: PathBuf,
+    // Whether the finished game has been saved
+    saved: bool,
+    // Opened on first use, some systems only keep copied text while it lives
+    clipboard: Option<arboard::Clipboard>,
+    // When resigning was first asked for, it takes a second press
+    resign_asked: Option<Instant>,
+}
+
+#[derive(Clone, Copy)]
+enum Orientation {
+    /// Our own pieces at the bottom
+    Auto,
+    /// Flipped by hand, `true` puts black at the bottom
+    Fixed(bool),
 }
 
 impl Gui {
 
-    pub fn new(ctx: &Context, layer: logic::Layer) -> Self {
+    pub fn new(ctx: &Context, layer: logic::Layer, pgn_dir: PathBuf) -> Self {
         
         Self {
             layer,
@@ -52,6 +90,139 @@ impl Gui {
                 queen:  Image::from_path(ctx, "/white_queen.png").unwrap(),
                 king:   Image::from_path(ctx, "/white_king.png").unwrap(),
             },
+            flash: None,
+            orientation: Orientation::Auto,
+            scroll: 0,
+            pgn_dir,
+            saved: false,
+            clipboard: None,
+            resign_asked: None,
+        }
+    }
+}
+
+impl Gui {
+
+    /// Whether black is at the bottom
+    fn flipped(&self) -> bool {
+
+        match self.orientation {
+            Orientation::Auto => matches!(self.layer.get_player(), Some(logic::Player::Black)),
+            Orientation::Fixed(flipped) => flipped,
+        }
+    }
+
+    fn save_pgn(&self) {
+
+        let state = self.layer.get_state();
+        let joever = match state.winner() {
+            Some(logic::Player::White) => protocol::Joever::White,
+            Some(logic::Player::Black) => protocol::Joever::Black,
+            None if matches!(state, logic::State::Drawn { .. }) => protocol::Joever::Draw,
+            None => protocol::Joever::Ongoing,
+        };
+
+        // Our side gets the local user name, the other is unknown
+        let me = env::var("USER")
+            .or_else(|_| env::var("USERNAME"))
+            .unwrap_or_else(|_| "?".to_string());
+        let (white, black) = match self.layer.get_player() {
+            Some(logic::Player::White) => (me.as_str(), "?"),
+            Some(logic::Player::Black) => ("?", me.as_str()),
+            None => (me.as_str(), me.as_str()),
+        };
+
+        let start = self.layer.get_start_position();
+        match pgn::save(&self.pgn_dir, start.as_ref(), self.layer.get_history(), white, black, joever) {
+            Ok(path) => println!("Saved game to {}", path.display()),
+            Err(e) => println!("Could not save game: {}", e),
+        }
+    }
+
+    fn copy_fen(&mut self) {
+
+        let fen = self.layer.get_fen();
+        println!("Position: {}", fen);
+
+        if self.clipboard.is_none() {
+            match arboard::Clipboard::new() {
+                Ok(clipboard) => self.clipboard = Some(clipboard),
+                Err(e) => println!("No clipboard: {}", e),
+            }
+        }
+
+        if let Some(clipboard) = &mut self.clipboard {
+            match clipboard.set_text(fen) {
+                Ok(()) => println!("Copied position to clipboard"),
+                Err(e) => println!("Could not copy position: {}", e),
+            }
+        }
+    }
+
+    fn resign_pending(&self) -> bool {
+
+        self.resign_asked.is_some_and(|asked| asked.elapsed() < RESIGN_CONFIRM_TIME)
+    }
+
+    /// Resigns on the second press, so a stray key doesn't lose the game
+    fn ask_resign(&mut self) {
+
+        if !self.layer.get_state().is_playing() {
+            return;
+        }
+
+        if self.resign_pending() {
+            self.resign_asked = None;
+            self.layer.resign();
+        } else {
+            self.resign_asked = Some(Instant::now());
+        }
+    }
+
+    fn draw_offer_hint(&self) -> Option<String> {
+
+        let offerer = self.layer.get_draw_offer()?;
+        Some(match self.layer.get_player() {
+            Some(player) if player == offerer => "Draw offered".to_string(),
+            Some(_) => "Opponent offers a draw    D: accept    N: decline".to_string(),
+            // Both sides share the screen, only the one to move answers
+            None if self.to_move() == Some(offerer) => format!("{:?} offered a draw", offerer),
+            None => format!("{:?} offers a draw    D: accept    N: decline", offerer),
+        })
+    }
+
+    /// Whose turn it is, from the position the layer reports
+    fn to_move(&self) -> Option<logic::Player> {
+
+        fen::parse(&self.layer.get_fen()).ok().map(|position| position.to_move)
+    }
+
+    fn takeback_hint(&self) -> Option<String> {
+
+        let requester = self.layer.get_takeback_request()?;
+        Some(if Some(requester) == self.layer.get_player() {
+            "Takeback requested".to_string()
+        } else {
+            "Opponent asks to take back a move    U: accept    N: decline".to_string()
+        })
+    }
+
+    fn image(&self, piece: logic::Piece, player: logic::Player) -> &Image {
+
+        use logic::{ Player::*, Piece::*, };
+
+        let images = match player {
+            White => &self.white,
+            Black => &self.black,
+        };
+
+        match piece {
+            Pawn   => &images.pawn,
+            Rook   => &images.rook,
+            Knight => &images.knight,
+            Bishop => &images.bishop,
+            Queen  => &images.queen,
+            King   => &images.king,
         }
     }
 }
@@ -60,6 +231,14 @@ impl event::EventHandler<GameError> for  Gui {
 
     fn update(&mut self, _ctx: &mut Context) -> GameResult {
         self.layer.update();
+
+        // Keep finished games for later, again if a takeback reopened one
+        let over = self.layer.get_state().is_over();
+        if over && !self.saved {
+            self.save_pgn();
+        }
+        self.saved = over;
+
         Ok(())
     }
 
@@ -88,56 +267,154 @@ impl event::EventHandler<GameError> for  Gui {
             board_param,
         );
 
+        use logic::State::*;
         if let SelectMove { from, } = self.layer.get_state() {
-            highlight_square(ctx, &mut canvas, from.0, from.1);
+            highlight_square(ctx, &mut canvas, self.flipped(), from);
+        }
+
+        if let Some((square, at)) = self.flash {
+            let left = 1. - at.elapsed().as_secs_f32() / FLASH_TIME.as_secs_f32();
+            if left > 0. {
+                fill_square(ctx, &mut canvas, self.flipped(), square, Color::from([0.9, 0.2, 0.2, 0.6 * left]));
+            } else {
+                self.flash = None;
+            }
         }
 
         // Draw pieces
-        for x in 0..8u8 {
-            for y in 0..8u8 {
+        for square in logic::Square::all() {
 
-                match self.layer.get_piece_at(x, y) {
-                    None => (),
-                    Some(piece) => {
+            match self.layer.get_piece_at(square) {
+                None => (),
+                Some((piece, player)) => {
 
-                        use logic::{ Player::*, Piece::*, };
+                    let image = self.image(piece, player);
+                    let (offset, scale) = piece_transform(ctx, self.flipped(), square, image);
 
-                        let images = match piece.1 {
-                            White => &self.white,
-                            Black => &self.black,
-                        };
+                    let draw_param = DrawParam::new()
+                        .dest(offset)
+                        .scale(scale);
 
-                        let image = match piece.0 {
-                            Pawn   => &images.pawn,
-                            Rook   => &images.rook,
-                            Knight => &images.knight,
-                            Bishop => &images.bishop,
-                            Queen  => &images.queen,
-                            King   => &images.king,
-                        };
+                    canvas.draw(image, draw_param);
+                }
+            }
+        }
 
-                        let (offset, scale) = piece_transform(ctx, x, y, image);
+        // Draw legal moves
+        if let SelectMove { from, } = self.layer.get_state() {
 
-                        let draw_param = DrawParam::new()
-                            .dest(offset)
-                            .scale(scale);
+            for square in self.layer.get_destinations(from) {
+                let capture = self.layer.get_piece_at(square).is_some();
+                mark_square(ctx, &mut canvas, self.flipped(), square, capture);
+            }
+        }
 
-                        canvas.draw(image, draw_param);
-                    }
+        // Draw promotion chooser
+        if let SelectPromotion { from, at, } = self.layer.get_state() {
+
+            if let Some((_, player)) = self.layer.get_piece_at(from) {
+
+                for (square, piece) in promotion_squares(at) {
+
+                    fill_square(ctx, &mut canvas, self.flipped(), square, Color::from([0.9, 0.9, 0.9, 0.9]));
+
+                    let image = self.image(piece, player);
+                    let (offset, scale) = piece_transform(ctx, self.flipped(), square, image);
+
+                    let draw_param = DrawParam::new()
+                        .dest(offset)
+                        .scale(scale);
+
+                    canvas.draw(image, draw_param);
                 }
             }
         }
 
-        use logic::State::*;
+        draw_history(ctx, &mut canvas, self.layer.get_history(), self.scroll);
+
+        // The clock of whoever sits at the top goes on top
+        let (top, bottom) = if self.flipped() {
+            (logic::Player::White, logic::Player::Black)
+        } else {
+            (logic::Player::Black, logic::Player::White)
+        };
+        for (player, row) in [(top, 0), (bottom, PANEL_LINES - 1)] {
+            if let Some(left) = self.layer.get_clock(player) {
+                draw_clock(ctx, &mut canvas, row, left);
+            }
+        }
+
         match self.layer.get_state() {
             OpponentTurn => 
                 draw_text(ctx, &mut canvas, "Opponents turn".to_string()),
             CheckMate(player) => {
                 draw_text(ctx, &mut canvas, format!("{:?} won!", player));
             },
+            OutOfTime { winner, } => {
+                draw_text(ctx, &mut canvas, format!("{:?} won on time!", winner));
+            },
+            Resigned { winner, } => {
+                draw_text(ctx, &mut canvas, format!("{:?} resigned", winner.other()));
+                draw_hint(ctx, &mut canvas, format!("{:?} won", winner));
+            },
+            Drawn { reason, } => {
+                let text = match reason {
+                    logic::DrawReason::Stalemate            => "Stalemate",
+                    logic::DrawReason::InsufficientMaterial => "Neither side can mate",
+                    logic::DrawReason::Repetition           => "Position repeated three times",
+                    logic::DrawReason::FiftyMoves           => "Fifty moves without progress",
+                    logic::DrawReason::Agreement            => "Agreed to a draw",
+                    logic::DrawReason::Declared             => "Server declared a draw",
+                };
+                draw_text(ctx, &mut canvas, "Draw!".to_string());
+                draw_hint(ctx, &mut canvas, text.to_string());
+            },
+            Disconnected { reason, } => {
+                let text = match reason {
+                    logic::Disconnect::Closed  => "Opponent left",
+                    logic::Disconnect::Broken  => "Connection lost",
+                    logic::Disconnect::Invalid => "Opponent is confused",
+                    logic::Disconnect::Refused => "Server refused",
+                };
+                // Spectators have no seat to wait in or try again for
+                let hint = match (reason, self.layer.get_player()) {
+                    (_, None) => "Q: quit",
+                    (logic::Disconnect::Refused, _) => "Q: quit    R: try again",
+                    _ => "Q: quit    R: wait for opponent",
+                };
+                draw_text(ctx, &mut canvas, text.to_string());
+                draw_hint(ctx, &mut canvas, hint.to_string());
+            },
+            Watching { to_move, } => {
+                draw_hint(ctx, &mut canvas, format!("{:?} to move", to_move));
+            },
+            Reconnecting => {
+                draw_text(ctx, &mut canvas, "Waiting for opponent".to_string());
+                draw_hint(ctx, &mut canvas, "Q: quit".to_string());
+            },
+            WaitingForOpponent { addr, } => {
+                draw_text(ctx, &mut canvas, "Waiting for opponent".to_string());
+                draw_hint(ctx, &mut canvas, format!("Join at {}    Q: cancel", addr));
+            },
+            Connecting { addr, attempt, } => {
+                draw_text(ctx, &mut canvas, format!("Connecting to {}", addr));
+                let hint = match attempt {
+                    1 => "Q: cancel".to_string(),
+                    _ => format!("Attempt {}    Q: cancel", attempt),
+                };
+                draw_hint(ctx, &mut canvas, hint);
+            },
             _ => (),
         }
 
+        if self.layer.get_state().is_playing() {
+            if self.resign_pending() {
+                draw_hint(ctx, &mut canvas, "Press X again to resign".to_string());
+            } else if let Some(hint) = self.takeback_hint().or_else(|| self.draw_offer_hint()) {
+                draw_hint(ctx, &mut canvas, hint);
+            }
+        }
+
         canvas.finish(ctx).unwrap();
 
         Ok(())
@@ -156,21 +433,35 @@ impl event::EventHandler<GameError> for  Gui {
         match button {
             Left => {
                 
-                let (x, y) = square_from_pos(ctx, x, y);
-
-                let valid = x >= 0 && x < 8 &&
-                    y >= 0 && y < 8;
+                let square = square_from_pos(ctx, self.flipped(), x, y);
 
                 use logic::State::*;
                 match self.layer.get_state().clone() {
                     SelectPiece => {
-                        if valid {
-                            self.layer.select_piece((x as u8, y as u8));
+                        if let Some(at) = square {
+                            self.layer.select_piece(at);
                         }
                     },
-                    SelectMove { from: _, } => {
-                        if valid {
-                            self.layer.play_move((x as u8, y as u8));
+                    SelectMove { from, } => {
+                        if let Some(dst) = square {
+                            // Layers that know the legal moves refuse the rest
+                            let destinations = self.layer.get_destinations(from);
+                            if !destinations.is_empty() && !destinations.contains(&dst) {
+                                self.flash = Some((dst, Instant::now()));
+                            }
+
+                            self.layer.play_move(dst);
+                        }
+                    },
+                    SelectPromotion { from: _, at, } => {
+                        let choice = promotion_squares(at)
+                            .into_iter()
+                            .find(|(choice, _)| Some(*choice) == square);
+
+                        // Clicking anywhere else puts the pawn back
+                        match choice {
+                            Some((_, piece)) => self.layer.select_promotion(piece),
+                            None => self.layer.cancel_promotion(),
                         }
                     },
                     _ => (),
@@ -181,18 +472,72 @@ impl event::EventHandler<GameError> for  Gui {
 
         Ok(())
     }
+
+    fn mouse_wheel_event(&mut self, _ctx: &mut Context, _x: f32, y: f32) -> GameResult {
+
+        let lines = history_lines(self.layer.get_history()).len();
+        let max = lines.saturating_sub(HISTORY_LINES);
+
+        if y > 0. {
+            self.scroll = (self.scroll + 1).min(max);
+        } else if y < 0. {
+            self.scroll = self.scroll.saturating_sub(1);
+        }
+
+        Ok(())
+    }
+
+    fn key_down_event(
+        &mut self,
+        ctx: &mut Context,
+        input: keyboard::KeyInput,
+        _repeated: bool,
+    ) -> GameResult {
+
+        use keyboard::KeyCode::*;
+
+        match input.keycode {
+            Some(Escape) if matches!(self.layer.get_state(), logic::State::SelectPromotion { .. }) =>
+                self.layer.cancel_promotion(),
+            Some(Escape) | Some(Q) => ctx.request_quit(),
+            Some(R) => self.layer.reconnect(),
+            Some(F) => self.orientation = Orientation::Fixed(!self.flipped()),
+            Some(A) => self.orientation = Orientation::Auto,
+            Some(S) => self.save_pgn(),
+            Some(C) => self.copy_fen(),
+            Some(X) => self.ask_resign(),
+            Some(D) => self.layer.offer_draw(),
+            Some(U) => self.layer.request_takeback(),
+            Some(N) => {
+                self.layer.decline_takeback();
+                self.layer.decline_draw();
+            },
+            Some(Left) => self.layer.seek(logic::Seek::Back),
+            Some(Right) => self.layer.seek(logic::Seek::Forward),
+            Some(Home) => self.layer.seek(logic::Seek::Start),
+            Some(End) => self.layer.seek(logic::Seek::End),
+            _ => (),
+        }
+
+        Ok(())
+    }
+}
+
+fn highlight_square(ctx: &Context, canvas: &mut Canvas, flipped: bool, at: logic::Square) {
+
+    fill_square(ctx, canvas, flipped, at, Color::from([0.3, 0.3, 0.9, 0.5]));
 }
 
-fn highlight_square(ctx: &Context, canvas: &mut Canvas, x: u8, y: u8) {
+fn fill_square(ctx: &Context, canvas: &mut Canvas, flipped: bool, at: logic::Square, color: Color) {
 
     let rect = Mesh::new_rectangle(
         ctx,
         DrawMode::Fill(FillOptions::DEFAULT),
         Rect::new(0.0, 0.0, SQUARE_OFFSET as f32, SQUARE_OFFSET as f32),
-        Color::from([0.3, 0.3, 0.9, 0.5])
+        color,
     ).unwrap();
 
-    let (offset, scale) = square_transform(ctx, x, y);
+    let (offset, scale) = square_transform(ctx, flipped, at);
     let param = DrawParam::new()
         .dest(offset)
         .scale(scale);
@@ -200,39 +545,164 @@ fn highlight_square(ctx: &Context, canvas: &mut Canvas, x: u8, y: u8) {
     canvas.draw(&rect, param);
 }
 
+/// Marks a square as a legal destination, with a ring around pieces that
+/// can be captured and a dot on empty squares
+fn mark_square(ctx: &Context, canvas: &mut Canvas, flipped: bool, at: logic::Square, capture: bool) {
+
+    let center = Vec2::splat(SQUARE_OFFSET as f32 / 2.);
+    let color = Color::from([0.1, 0.1, 0.1, 0.4]);
+
+    let circle = if capture {
+        Mesh::new_circle(ctx, DrawMode::stroke(2.), center, SQUARE_OFFSET as f32 / 2. - 1., 0.1, color)
+    } else {
+        Mesh::new_circle(ctx, DrawMode::fill(), center, SQUARE_OFFSET as f32 / 6., 0.1, color)
+    }.unwrap();
+
+    let (offset, scale) = square_transform(ctx, flipped, at);
+    let param = DrawParam::new()
+        .dest(offset)
+        .scale(scale);
+
+    canvas.draw(&circle, param);
+}
+
+/// The squares of the promotion chooser, stacked from `at` towards the
+/// middle of the board
+fn promotion_squares(at: logic::Square) -> [(logic::Square, logic::Piece); 4] {
+
+    let mut squares = [(at, logic::Piece::Queen); 4];
+
+    for (i, piece) in logic::PROMOTIONS.into_iter().enumerate() {
+        let rank = if at.rank < 4 { at.rank + i as u8 } else { at.rank - i as u8 };
+        squares[i] = (logic::Square { rank, ..at }, piece);
+    }
+
+    squares
+}
+
+/// The board and the history panel to its right are centred together
 fn board_transform(ctx: &Context) -> (Vec2, Vec2) {
 
     let (w, h) = ctx.gfx.size();
-    let s = if w > h { h } else { w };
+    let s = (w / (1. + PANEL_WIDTH)).min(h);
     let t = s / (BOARD_SIZE as f32);
-    let offset = if w > h {
-        Vec2 {
-            x: (w - h) / 2.,
-            y: 0.,
-        }
-    } else {
-        Vec2 {
-            x: 0.,
-            y: (h - w) / 2.,
-        }
+    let offset = Vec2 {
+        x: (w - s * (1. + PANEL_WIDTH)) / 2.,
+        y: (h - s) / 2.,
     };
 
     (offset, Vec2::splat(t))
 }
 
-fn square_transform(ctx: &Context, x: u8, y: u8) -> (Vec2, Vec2) {
+fn panel_rect(ctx: &Context) -> Rect {
+
+    let (offset, scale) = board_transform(ctx);
+    let s = BOARD_SIZE as f32 * scale.x;
+
+    Rect::new(offset.x + s, offset.y, s * PANEL_WIDTH, s)
+}
+
+/// Pairs up moves into numbered lines like "1. e4 e5"
+fn history_lines(history: &[String]) -> Vec<String> {
+
+    history.chunks(2)
+        .enumerate()
+        .map(|(i, pair)| format!("{}. {}", i + 1, pair.join(" ")))
+        .collect()
+}
+
+/// Draws the latest moves that fit, minus the `scroll` last lines
+fn draw_history(ctx: &Context, canvas: &mut Canvas, history: &[String], scroll: usize) {
+
+    let rect = panel_rect(ctx);
+    let background = Mesh::new_rectangle(
+        ctx,
+        DrawMode::Fill(FillOptions::DEFAULT),
+        rect,
+        Color::from([0.1, 0.1, 0.1, 0.3]),
+    ).unwrap();
+
+    canvas.draw(&background, DrawParam::new());
+
+    let lines = history_lines(history);
+    let end = lines.len().saturating_sub(scroll);
+    let start = end.saturating_sub(HISTORY_LINES);
+    let line_height = rect.h / PANEL_LINES as f32;
+
+    for (i, line) in lines[start..end].iter().enumerate() {
+
+        // Below the top clock
+        let row = i + 1;
+        let param = DrawParam::new()
+            .color(Color::from([0.9, 0.9, 0.9, 1.0]))
+            .dest(Vec2::new(rect.x + line_height / 2., rect.y + line_height * row as f32));
+
+        canvas.draw(
+            Text::new(line.as_str())
+                .set_font("Handjet")
+                .set_scale(line_height),
+            param,
+        );
+    }
+}
+
+/// Draws time left in `row` of the panel, red when it runs low
+fn draw_clock(ctx: &Context, canvas: &mut Canvas, row: usize, left: Duration) {
+
+    let rect = panel_rect(ctx);
+    let line_height = rect.h / PANEL_LINES as f32;
+
+    let secs = left.as_secs();
+    let text = if left < Duration::from_secs(10) {
+        format!("{}:{:02}.{}", secs / 60, secs % 60, left.subsec_millis() / 100)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    };
+    let color = if left < LOW_TIME {
+        Color::from([0.9, 0.2, 0.2, 1.0])
+    } else {
+        Color::from([0.9, 0.9, 0.9, 1.0])
+    };
+
+    let param = DrawParam::new()
+        .color(color)
+        .dest(Vec2::new(rect.x + line_height / 2., rect.y + line_height * row as f32));
+
+    canvas.draw(
+        Text::new(text)
+            .set_font("Handjet")
+            .set_scale(line_height),
+        param,
+    );
+}
+
+/// The screen column and row a square is drawn in, white ends up at the
+/// bottom unless the board is flipped
+fn square_cell(flipped: bool, at: logic::Square) -> (u8, u8) {
+
+    if flipped {
+        (7 - at.file, at.rank)
+    } else {
+        (at.file, 7 - at.rank)
+    }
+}
+
+fn square_transform(ctx: &Context, flipped: bool, at: logic::Square) -> (Vec2, Vec2) {
+
+    let (column, row) = square_cell(flipped, at);
 
     let (mut offset, scale) = board_transform(ctx);
     offset += BOARD_OFFSET as f32 * scale;
     offset += Vec2 {
-        x: scale.x * SQUARE_OFFSET as f32 * x as f32,
-        y: scale.y * SQUARE_OFFSET as f32 * y as f32,
+        x: scale.x * SQUARE_OFFSET as f32 * column as f32,
+        y: scale.y * SQUARE_OFFSET as f32 * row as f32,
     };
 
     (offset, scale)
 }
 
-fn square_from_pos(ctx: &Context, x: f32, y: f32) -> (i8, i8) {
+/// Inverse of `square_transform`, `None` off the board
+fn square_from_pos(ctx: &Context, flipped: bool, x: f32, y: f32) -> Option<logic::Square> {
     
     let (offset, scale) = board_transform(ctx);
     let mut pos = Vec2 { x, y, };
@@ -241,7 +711,17 @@ fn square_from_pos(ctx: &Context, x: f32, y: f32) -> (i8, i8) {
     pos /= scale;
     pos -= Vec2::splat(BOARD_OFFSET as f32);
     pos /= SQUARE_OFFSET as f32;
-    (pos.x as i8, pos.y as i8)
+
+    if !(0. ..8.).contains(&pos.x) || !(0. ..8.).contains(&pos.y) {
+        return None;
+    }
+
+    let (column, row) = (pos.x as u8, pos.y as u8);
+    if flipped {
+        logic::Square::new(row, 7 - column)
+    } else {
+        logic::Square::new(7 - row, column)
+    }
 }
 
 fn draw_text(ctx: &Context, canvas: &mut Canvas, text: String) {
@@ -271,17 +751,36 @@ fn draw_text(ctx: &Context, canvas: &mut Canvas, text: String) {
     );
 }
 
+/// Draws a line of smaller text below the text drawn by `draw_text`
+fn draw_hint(ctx: &Context, canvas: &mut Canvas, text: String) {
+
+    let (w, h) = ctx.gfx.size();
+    let pos = Vec2::new(w / 2., h / 2. + 80.);
+
+    let param = DrawParam::new()
+        .color(Color::from([0.9, 0.9, 0.9, 1.0]))
+        .dest(pos);
+
+    canvas.draw(
+        Text::new(text)
+            .set_font("Handjet")
+            .set_layout(TextLayout::center())
+            .set_scale(40.),
+        param,
+    );
+}
+
 fn piece_transform(
     ctx: &Context,
-    x: u8,
-    y: u8,
+    flipped: bool,
+    at: logic::Square,
     image: &Image
 ) -> (Vec2, Vec2) {
 
     let w = image.width() as f32;
     let h = image.height() as f32;
 
-    let (mut offset, scale) = square_transform(ctx, x, y); 
+    let (mut offset, scale) = square_transform(ctx, flipped, at); 
 
     offset += Vec2 {
         x: scale.x * (SQUARE_OFFSET as f32 - w) / 2.,
@@ -291,7 +790,7 @@ fn piece_transform(
     (offset, scale)
 }
 
-pub fn run(layer: logic::Layer) {
+pub fn run(layer: logic::Layer, pgn_dir: PathBuf) {
 
     let mut config = conf::Conf::new();         
 
@@ -309,7 +808,7 @@ pub fn run(layer: logic::Layer) {
         FontData::from_path(&ctx, "/Handjet-Medium.ttf").unwrap()
     );
     
-    let gui = Gui::new(&ctx, layer);
+    let gui = Gui::new(&ctx, layer, pgn_dir);
 
     event::run(ctx, event_loop, gui);
 }
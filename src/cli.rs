@@ -0,0 +1,236 @@
+use clap::{ Parser, Subcommand, ValueEnum, };
+
+use crate::logic;
+use crate::fen;
+use crate::search;
+use crate::clock::TimeControl;
+
+use std::collections::hash_map::RandomState;
+use std::hash::{ BuildHasher, Hasher, };
+use std::net::{ IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs, };
+use std::path::PathBuf;
+
+/// Servers listen here and clients connect here unless told otherwise
+pub const PORT: u16 = 8384;
+// Where games are saved unless --pgn-dir says otherwise
+const PGN_DIR: &str = "games";
+
+/// Play chess over the network, on one screen or against an engine
+#[derive(Parser)]
+#[command(version)]
+pub struct Cli {
+
+    #[command(subcommand)]
+    pub command: Command,
+
+    /// Save finished games to <DIR>
+    #[arg(long, value_name = "DIR", global = true, default_value = PGN_DIR)]
+    pub pgn_dir: PathBuf,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+
+    /// Host a game and play in it
+    Serve {
+        /// Start from this position instead of the usual one
+        #[arg(long, value_parser = parse_fen)]
+        fen: Option<fen::Position>,
+        /// Time control, like 5+3: minutes each, then seconds added after
+        /// every move, optionally followed by a delay like 5+0d2
+        #[arg(long, value_parser = parse_time_control)]
+        time: Option<TimeControl>,
+        /// Referee a game between two clients instead, without a window
+        #[arg(long)]
+        headless: bool,
+        /// Host any number of games between clients, without a window
+        #[arg(long, conflicts_with = "headless")]
+        lobby: bool,
+        /// Address to listen on, like :: for IPv6 or 127.0.0.1 to only
+        /// allow this computer
+        #[arg(long, default_value_t = IpAddr::V4(Ipv4Addr::UNSPECIFIED))]
+        bind: IpAddr,
+        /// Port to listen on
+        #[arg(long, default_value_t = PORT)]
+        port: u16,
+    },
+
+    /// Play against someone on the same screen
+    Local,
+
+    /// Connect to a server and play against it
+    Connect {
+        /// Host name or IP address, optionally followed by :<PORT>
+        #[arg(value_parser = parse_addr)]
+        addr: Addrs,
+        /// Colour to play
+        #[arg(long, value_enum, default_value_t = Colour::White)]
+        color: Colour,
+        /// Open a game on a lobby for someone else to join
+        #[arg(long)]
+        new: bool,
+        /// Join the open game with this ID on a lobby. Without this or
+        /// --new, you play anyone waiting for your colour.
+        #[arg(long, value_name = "ID", conflicts_with = "new")]
+        game: Option<u32>,
+    },
+
+    /// Watch the game on a server
+    Spectate {
+        /// Host name or IP address, optionally followed by :<PORT>
+        #[arg(value_parser = parse_addr)]
+        addr: Addrs,
+        /// Watch the game with this ID on a lobby
+        #[arg(long, value_name = "ID")]
+        game: Option<u32>,
+    },
+
+    /// List the games on a lobby
+    Lobby {
+        /// Host name or IP address, optionally followed by :<PORT>
+        #[arg(value_parser = parse_addr)]
+        addr: Addrs,
+    },
+
+    /// Play against a UCI engine
+    Engine {
+        /// The engine's executable
+        path: String,
+        /// Milliseconds the engine gets per move
+        #[arg(long, value_name = "MS")]
+        movetime: Option<u64>,
+        /// Plies the engine searches per move
+        #[arg(long)]
+        depth: Option<u32>,
+    },
+
+    /// Play against the built-in AI
+    Ai {
+        /// Plies to search
+        #[arg(long, default_value_t = search::Budget::default().depth)]
+        depth: u32,
+        /// Milliseconds to search for at most
+        #[arg(long, value_name = "MS")]
+        time: Option<u64>,
+    },
+
+    /// Step through a game saved as PGN
+    Replay {
+        /// PGN file to read
+        file: String,
+        /// Which game of the file, counting from 1
+        #[arg(long, default_value_t = 1, value_parser = parse_game_number)]
+        game: usize,
+    },
+}
+
+/// Every address a host resolved to, connections try them in turn
+#[derive(Clone)]
+pub struct Addrs(pub Vec<SocketAddr>);
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Colour {
+    White,
+    Black,
+    Random,
+}
+
+impl Colour {
+
+    pub fn player(self) -> logic::Player {
+
+        match self {
+            Colour::White => logic::Player::White,
+            Colour::Black => logic::Player::Black,
+            Colour::Random => random_player(),
+        }
+    }
+}
+
+fn random_player() -> logic::Player {
+
+    // Hashers are randomly seeded, good enough for a coin flip
+    if RandomState::new().build_hasher().finish() & 1 == 0 {
+        logic::Player::White
+    } else {
+        logic::Player::Black
+    }
+}
+
+fn parse_game_number(game: &str) -> Result<usize, String> {
+
+    match game.parse() {
+        Ok(0) => Err("games are counted from 1".to_string()),
+        Ok(game) => Ok(game),
+        Err(_) => Err("expected a number".to_string()),
+    }
+}
+
+fn parse_fen(fen: &str) -> Result<fen::Position, String> {
+
+    fen::parse(fen).map_err(|e| e.to_string())
+}
+
+fn parse_time_control(control: &str) -> Result<TimeControl, String> {
+
+    control.parse().map_err(|_| "expected <minutes>+<increment>[d<delay>]".to_string())
+}
+
+/// Reads `host[:port]`, where the host is a name or an IP address. IPv6
+/// addresses need brackets when followed by a port, like [::1]:8384.
+fn parse_addr(addr: &str) -> Result<Addrs, String> {
+
+    if let Ok(addr) = addr.parse() {
+        return Ok(Addrs(vec![addr]));
+    }
+
+    // Bare IPv6 addresses have colons of their own
+    let bare = addr.strip_prefix('[').and_then(|addr| addr.strip_suffix(']')).unwrap_or(addr);
+    if let Ok(ip) = bare.parse() {
+        return Ok(Addrs(vec![SocketAddr::new(ip, PORT)]));
+    }
+
+    let (host, port) = match addr.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().map_err(|_| format!("invalid port {}", port))?),
+        None => (addr, PORT),
+    };
+
+    // Names often resolve to both IPv6 and IPv4, only one may answer
+    let addrs: Vec<_> = (host, port).to_socket_addrs()
+        .map_err(|e| format!("could not resolve {}: {}", host, e))?
+        .collect();
+    if addrs.is_empty() {
+        return Err(format!("no address found for {}", host));
+    }
+
+    Ok(Addrs(addrs))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn addresses() {
+
+        let Addrs(addrs) = parse_addr("127.0.0.1").unwrap();
+        assert_eq!(addrs, ["127.0.0.1:8384".parse().unwrap()]);
+
+        let Addrs(addrs) = parse_addr("::1").unwrap();
+        assert_eq!(addrs, ["[::1]:8384".parse().unwrap()]);
+
+        let Addrs(addrs) = parse_addr("[::1]:9").unwrap();
+        assert_eq!(addrs, ["[::1]:9".parse().unwrap()]);
+
+        assert!(parse_addr("localhost:x").is_err());
+    }
+
+    #[test]
+    fn names_keep_every_address() {
+
+        let Addrs(addrs) = parse_addr("localhost:9").unwrap();
+        let expected: Vec<_> = ("localhost", 9).to_socket_addrs().unwrap().collect();
+        assert_eq!(addrs, expected);
+    }
+}
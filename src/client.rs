@@ -4,52 +4,463 @@ use chess_network_protocol::{
     ServerToClient          as Stc,
     ClientToServer          as Cts,
     ServerToClientHandshake as StcHand,
-    ClientToServerHandshake as CtsHand,
 };
 
-use crate::logic;
+use crate::logic::{ self, logic_to_proto, proto_to_logic, };
+use crate::fen;
+use crate::clock::{ self, Clock, };
+use crate::draw;
+use crate::takeback;
+use crate::san;
+use crate::lobby;
+use crate::extensions::{ ClientHandshake, Extensions, };
 use crate::tcp_handler::{ self as tcp, TcpHandler, };
 
-use std::net::TcpStream;
+use std::io;
+use std::net::{ SocketAddr, TcpStream, };
+use std::sync::mpsc::{ self, Receiver, TryRecvError, };
+use std::thread;
+use std::time::{ Duration, Instant, };
+
+// How long to wait between reconnection attempts
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+// How long to wait for the server to answer before trying again
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+// How long a server we reconnect to may take to send its handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The stream to a server after the handshake, with its half of it and
+/// the colour we play
+type Connection = (TcpStream, StcHand, logic::Player);
 
 pub struct Client {
 
+    // Tried in turn, the first is shown while connecting
+    addrs:       Vec<SocketAddr>,
     player:      logic::Player,
+    // How to find the game if the server is a lobby
+    entry:       lobby::Entry,
+    // Set up on its own thread, the handshake waits for the game to start
+    attempt:     Option<Receiver<Result<Connection, logic::Disconnect>>>,
     board:       [[protocol::Piece; 8]; 8],
+    // Legal moves, if the server sends them
+    moves:       Vec<protocol::Move>,
     state:       logic::State,
-    tcp_handler: TcpHandler<Stc, Cts>,
+    history:     Vec<String>,
+    // Follows the server's clock, which decides when time runs out
+    clock:       Option<Clock>,
+    // None until connected
+    tcp_handler: Option<TcpHandler<Stc, Cts>>,
+    // State to return to after reconnecting
+    resume:      logic::State,
+    last_retry:  Instant,
+    draw_offer:  Option<logic::Player>,
+    takeback:    Option<logic::Player>,
+    // What the server named in its handshake
+    extensions:  Extensions,
+    // The board after each move, to tell why the server drew the game
+    positions:   Vec<logic::Board>,
+    // Where the game started, None until connected
+    start:       Option<fen::Position>,
 }
 
 impl Client {
 
-    pub fn new(addr: String) -> logic::Layer {
+    /// `addrs` must not be empty
+    pub fn new(addrs: Vec<SocketAddr>, player: logic::Player, entry: lobby::Entry) -> logic::Layer {
 
+        let addr = addrs[0];
         println!("Connecting to address {}", addr);
-        let stream = TcpStream::connect(addr).expect("Could not connect to server");
-        println!("Connected!");
 
-        // We always wanna be white
-        // hehe
-        let ctsh = CtsHand {
-            server_color: protocol::Color::Black,
+        // The board stays empty until the server sends one
+        let board = [[protocol::Piece::None; 8]; 8];
+        let state = logic::State::Connecting { addr, attempt: 1, };
+
+        let mut client = Self {
+            addrs,
+            player,
+            entry,
+            attempt: None,
+            board,
+            moves: Vec::new(),
+            state,
+            history: Vec::new(),
+            clock: None,
+            tcp_handler: None,
+            resume: state,
+            last_retry: Instant::now(),
+            draw_offer: None,
+            takeback: None,
+            extensions: Extensions::default(),
+            positions: Vec::new(),
+            start: None,
         };
-        tcp::write(&stream, ctsh);
+        client.start_attempt();
+
+        Box::new(client)
+    }
+
+    fn start_attempt(&mut self) {
+
+        let (sender, receiver) = mpsc::channel();
+        let (addrs, player, entry) = (self.addrs.clone(), self.player, self.entry);
+        // Coming back to a game that started, no lobby to get through
+        let rejoining = matches!(self.state, logic::State::Reconnecting);
+        thread::spawn(move || {
+            let attempt = if rejoining {
+                rejoin(&addrs, player)
+            } else {
+                establish(&addrs, player, entry)
+            };
+            // Nobody is listening if the window closed meanwhile
+            let _ = sender.send(attempt);
+        });
+
+        self.attempt = Some(receiver);
+    }
+
+    /// What came of the attempt under way, if it's done. Starts the next
+    /// one a bit after the last failed.
+    fn poll_attempt(&mut self) -> Option<Result<Connection, logic::Disconnect>> {
+
+        let result = match &self.attempt {
+            Some(receiver) => match receiver.try_recv() {
+                Ok(result) => result,
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => Err(logic::Disconnect::Broken),
+            },
+            None => {
+                if self.last_retry.elapsed() >= RETRY_INTERVAL {
+                    self.start_attempt();
+                }
+                return None;
+            },
+        };
+        self.attempt = None;
+
+        Some(result)
+    }
+
+    /// Starts playing once an attempt gets through, or tries again a bit
+    /// after it fails
+    fn try_connect(&mut self) {
+
+        let (addr, attempt) = match self.state {
+            logic::State::Connecting { addr, attempt, } => (addr, attempt),
+            _ => return,
+        };
+
+        let result = match self.poll_attempt() {
+            Some(result) => result,
+            None => return,
+        };
+
+        match result {
+            Ok(connection) => self.connected(connection),
+            // Trying again won't change the lobby's mind, but the user may
+            Err(logic::Disconnect::Refused) => {
+                self.resume = logic::State::Connecting { addr, attempt: 1, };
+                self.state = logic::State::Disconnected { reason: logic::Disconnect::Refused, };
+            },
+            Err(_) => {
+                self.last_retry = Instant::now();
+                self.state = logic::State::Connecting { addr, attempt: attempt + 1, };
+            },
+        }
+    }
+
+    fn connected(&mut self, (stream, stch, player): Connection) {
 
-        let stch: StcHand = tcp::read(&stream);
         println!("Handshake complete!");
-        
-        let board = stch.board.clone();
 
-        let tcp_handler = TcpHandler::new(stream);
-        let state = logic::State::SelectPiece; // because we're white
-        let player = logic::Player::White;
+        let board = stch.board;
+        let moves = stch.moves;
+        let to_move = to_move(&board, &moves);
 
-        Box::new(Self {
-            player,
-            board,
-            state,
-            tcp_handler,
-        })
+        self.clock = clock::from_features(&stch.features).map(|control| {
+            println!("Playing with time control {}", control);
+            let mut clock = Clock::new(control);
+            clock.start(to_move);
+            clock
+        });
+
+        self.player = player;
+        self.board = board;
+        self.moves = moves;
+        self.extensions = Extensions::from_features(&stch.features);
+        self.positions = vec![logic_board(&board)];
+        self.start = Some(fen::Position::from_board(logic_board(&board), to_move, 1));
+        self.tcp_handler = Some(TcpHandler::new(stream));
+        self.state = if to_move == player {
+            logic::State::SelectPiece
+        } else {
+            logic::State::OpponentTurn
+        };
+        self.resume = self.state;
+    }
+}
+
+impl Client {
+
+    fn send_move(&mut self, from: logic::Square, dst: logic::Square, promotion: protocol::Piece) {
+
+        let (start_x, start_y) = from.to_protocol();
+        let (end_x, end_y) = dst.to_protocol();
+        let cts = Cts::Move(protocol::Move {
+            start_x,
+            start_y,
+            end_x,
+            end_y,
+            promotion,
+        });
+
+        self.state = logic::State::ResponsePending;
+        if let Err(e) = self.write(cts) {
+            self.disconnect(e);
+        }
+    }
+
+    /// Sends to the server, which is only there once connected
+    fn write(&self, cts: Cts) -> Result<(), tcp::Error> {
+
+        match &self.tcp_handler {
+            Some(tcp_handler) => tcp_handler.write(cts),
+            None => Err(tcp::Error::Closed),
+        }
+    }
+
+    /// Reads a message from the server, handling disconnects
+    fn receive(&mut self) -> Option<Stc> {
+
+        match self.tcp_handler.as_ref()?.read() {
+            Ok(stc) => stc,
+            Err(e) => {
+                self.disconnect(e);
+                None
+            },
+        }
+    }
+
+    /// Writes down a move the server confirmed, `board` is the position
+    /// after it
+    fn record(&mut self, mov: protocol::Move, board: &[[protocol::Piece; 8]; 8], mate: bool) {
+
+        if let Some(san) = protocol_san(&self.board, mov, board, mate) {
+            self.history.push(san);
+            self.positions.push(logic_board(board));
+        }
+    }
+
+    /// Writes down the move that turned our board into `board` while we
+    /// were gone. It's one of the legal moves we had, the one that left
+    /// its square for a square that now holds its piece.
+    fn record_missed(&mut self, board: &[[protocol::Piece; 8]; 8]) {
+
+        let missed = self.moves.iter().copied().find(|mov| {
+            let moved = proto_to_logic(self.board[mov.start_y][mov.start_x]);
+            let arrived = match proto_to_logic(mov.promotion) {
+                None => moved,
+                promotion => promotion,
+            };
+
+            proto_to_logic(board[mov.start_y][mov.start_x]).is_none()
+                && arrived.is_some()
+                && proto_to_logic(board[mov.end_y][mov.end_x]) == arrived
+        });
+
+        match missed {
+            Some(mov) => {
+                let mover = to_move(&self.board, &self.moves);
+                self.record(mov, board, false);
+                self.press_clock(mover, &protocol::Joever::Ongoing);
+            },
+            None => println!("Missed a move while disconnected, the history lacks it"),
+        }
+    }
+
+    /// Ends the turn of `player` on the clock, or stops it if the game
+    /// is over
+    fn press_clock(&mut self, player: logic::Player, joever: &protocol::Joever) {
+
+        if let Some(clock) = &mut self.clock {
+            if matches!(joever, protocol::Joever::Ongoing) {
+                clock.press(player);
+            } else {
+                clock.stop();
+            }
+        }
+    }
+
+    fn stop_clock(&mut self) {
+
+        if let Some(clock) = &mut self.clock {
+            clock.stop();
+        }
+    }
+
+    fn out_of_time(&mut self, winner: logic::Player) {
+
+        println!("Game over: {:?} ran out of time", winner.other());
+        self.stop_clock();
+        self.state = logic::State::OutOfTime { winner, };
+    }
+
+    fn opponent_resigned(&mut self) {
+
+        println!("Opponent resigned");
+        self.stop_clock();
+        self.state = logic::State::Resigned { winner: self.player, };
+    }
+
+    fn end_by_agreement(&mut self) {
+
+        self.stop_clock();
+        self.draw_offer = None;
+        self.state = logic::State::Drawn { reason: logic::DrawReason::Agreement, };
+    }
+
+    /// Handles the messages that can come on either turn, returns the rest
+    fn handle_any_turn(&mut self, stc: Stc) -> Option<Stc> {
+
+        match stc {
+            Stc::Resigned { .. } => self.opponent_resigned(),
+            // Either an offer or the answer to ours
+            Stc::Draw { .. } => {
+                if self.draw_offer == Some(self.player) {
+                    println!("Opponent accepted the draw");
+                    self.end_by_agreement();
+                } else {
+                    println!("Opponent offers a draw");
+                    self.draw_offer = Some(self.player.other());
+                }
+            },
+            Stc::Error { message, .. } if self.extensions.draw_declined && message == draw::DECLINED => {
+                println!("Opponent declined the draw");
+                self.draw_offer = None;
+            },
+            Stc::Error { message, .. } if self.extensions.takeback && message == takeback::REQUESTED => {
+                println!("Opponent asks to take back a move");
+                self.takeback = Some(self.player.other());
+            },
+            Stc::Error { message, .. } if self.extensions.takeback && message == takeback::DECLINED => {
+                println!("Opponent declined the takeback");
+                self.takeback = None;
+            },
+            Stc::Error { board, moves, message, .. }
+                if self.extensions.takeback && message == takeback::TAKEN_BACK => self.taken_back(board, moves),
+            Stc::Error { joever: protocol::Joever::White, message, .. } if message == clock::OUT_OF_TIME =>
+                self.out_of_time(logic::Player::White),
+            Stc::Error { joever: protocol::Joever::Black, message, .. } if message == clock::OUT_OF_TIME =>
+                self.out_of_time(logic::Player::Black),
+            stc => return Some(stc),
+        }
+
+        None
+    }
+
+    /// The server rewound to `board`, before the last move of whoever
+    /// asked
+    fn taken_back(&mut self, board: [[protocol::Piece; 8]; 8], moves: Vec<protocol::Move>) {
+
+        println!("Move taken back");
+        let requester = self.takeback.unwrap_or_else(|| to_move(&board, &moves));
+        let plies = takeback::plies(requester, self.side_to_move());
+        self.history.truncate(self.history.len().saturating_sub(plies));
+        self.positions.truncate(self.positions.len().saturating_sub(plies).max(1));
+
+        self.board = board;
+        self.moves = moves;
+        self.takeback = None;
+        self.draw_offer = None;
+
+        if let Some(clock) = &mut self.clock {
+            clock.stop();
+            clock.start(requester);
+        }
+        self.state = if requester == self.player {
+            logic::State::SelectPiece
+        } else {
+            logic::State::OpponentTurn
+        };
+    }
+
+    fn side_to_move(&self) -> logic::Player {
+
+        use logic::State::*;
+        match self.state {
+            SelectPiece | SelectMove { .. } | SelectPromotion { .. } | ResponsePending => self.player,
+            _ => self.player.other(),
+        }
+    }
+
+    /// The state after a move the server confirmed, `next` if the game
+    /// goes on
+    fn after_move(&self, joever: protocol::Joever, next: logic::State) -> logic::State {
+
+        match joever {
+            protocol::Joever::Ongoing => next,
+            protocol::Joever::White => logic::State::CheckMate(logic::Player::White),
+            protocol::Joever::Black => logic::State::CheckMate(logic::Player::Black),
+            protocol::Joever::Draw => logic::State::Drawn { reason: self.draw_reason(), },
+            protocol::Joever::Indeterminate => logic::State::Drawn { reason: logic::DrawReason::Declared, },
+        }
+    }
+
+    fn draw_reason(&self) -> logic::DrawReason {
+
+        let board = logic_board(&self.board);
+        draw::guess_reason(&board, &self.positions, &self.history, self.moves.is_empty())
+    }
+
+    fn disconnect(&mut self, e: tcp::Error) {
+
+        println!("Lost connection to server: {}", e);
+        // Like the server's, which waits for us to come back
+        self.stop_clock();
+        self.resume = self.state;
+        self.state = logic::State::Disconnected { reason: e.into(), };
+    }
+
+    /// Picks the game back up once an attempt gets through
+    fn try_reconnect(&mut self) {
+
+        let (stream, stch, _) = match self.poll_attempt() {
+            Some(Ok(connection)) => connection,
+            Some(Err(_)) => {
+                self.last_retry = Instant::now();
+                return;
+            },
+            None => return,
+        };
+        println!("Reconnected!");
+
+        // The board tells us if a move got lost while we were gone
+        let changed = (0..8).any(|x| (0..8).any(|y|
+            proto_to_logic(stch.board[x][y]) != proto_to_logic(self.board[x][y])
+        ));
+
+        if changed {
+            self.record_missed(&stch.board);
+        }
+
+        self.board = stch.board;
+        self.moves = stch.moves;
+        self.extensions = Extensions::from_features(&stch.features);
+        self.tcp_handler = Some(TcpHandler::new(stream));
+        self.state = match (self.resume, changed) {
+            (logic::State::ResponsePending, true)  => logic::State::OpponentTurn,
+            (logic::State::ResponsePending, false) => logic::State::SelectPiece,
+            (logic::State::OpponentTurn, true)     => logic::State::SelectPiece,
+            (state, _) => state,
+        };
+
+        // The server's clock was stopped while we were gone
+        if self.state.is_playing() {
+            let to_move = to_move(&self.board, &self.moves);
+            if let Some(clock) = &mut self.clock {
+                clock.start(to_move);
+            }
+        }
     }
 }
 
@@ -59,19 +470,40 @@ impl logic::Interface for Client {
         self.state
     } 
 
-    fn get_piece_at(&self, x: u8, y: u8) -> Option<(logic::Piece, logic::Player)> {
+    fn get_player(&self) -> Option<logic::Player> {
+
+        Some(self.player)
+    }
+
+    fn get_piece_at(&self, at: logic::Square) -> Option<(logic::Piece, logic::Player)> {
 
-        proto_to_logic(self.board[x as usize][y as usize])
+        let (x, y) = at.to_protocol();
+        proto_to_logic(self.board[y][x])
     }
 
-    fn select_piece(&mut self, at: (u8, u8)) {
+    fn get_destinations(&self, from: logic::Square) -> Vec<logic::Square> {
+
+        let targets = self.moves.iter()
+            .filter(|m| logic::Square::from_protocol(m.start_x, m.start_y) == Some(from))
+            .filter_map(|m| logic::Square::from_protocol(m.end_x, m.end_y));
+
+        // Promotions come as one move per piece, in any order
+        let mut destinations = Vec::new();
+        for to in targets {
+            if !destinations.contains(&to) {
+                destinations.push(to);
+            }
+        }
+        destinations
+    }
+
+    fn select_piece(&mut self, at: logic::Square) {
         
         if !matches!(self.state, logic::State::SelectPiece) {
             return;
         }
 
-        let piece = self.board[at.0 as usize][at.1 as usize];
-        let piece = match proto_to_logic(piece) {
+        let piece = match self.get_piece_at(at) {
             Some(piece) => piece,
             None => return,
         };
@@ -84,111 +516,402 @@ impl logic::Interface for Client {
         self.state = logic::State::SelectMove { from: at, };
     }
 
-    fn play_move(&mut self, dst: (u8, u8)) {
+    fn play_move(&mut self, dst: logic::Square) {
 
         let from = match self.state {
             logic::State::SelectMove { from } => from,
             _ => return,
         };
 
-        let cts = Cts::Move(protocol::Move {
-            start_x: from.1 as usize,
-            start_y: from.0 as usize,
-            end_x: dst.1 as usize,
-            end_y: dst.0 as usize,
-            // We don't support promotions
-            promotion: protocol::Piece::None,
-        });
+        // Only ask the server if it hasn't told us the legal moves
+        if !self.moves.is_empty() && !self.get_destinations(from).contains(&dst) {
+            self.state = logic::State::SelectPiece;
+            return;
+        }
 
-        self.tcp_handler.write(cts);        
-        self.state = logic::State::ResponsePending;
+        // Pawns reaching the last rank need a promotion first
+        if matches!(self.get_piece_at(from), Some((logic::Piece::Pawn, _)))
+            && dst.rank == self.player.promotion_rank()
+        {
+            self.state = logic::State::SelectPromotion { from, at: dst, };
+            return;
+        }
+
+        self.send_move(from, dst, protocol::Piece::None);
+    }
+
+    fn select_promotion(&mut self, piece: logic::Piece) {
+
+        let (from, at) = match self.state {
+            logic::State::SelectPromotion { from, at } => (from, at),
+            _ => return,
+        };
+
+        self.send_move(from, at, logic_to_proto(piece, self.player));
+    }
+
+    fn cancel_promotion(&mut self) {
+
+        if let logic::State::SelectPromotion { from, .. } = self.state {
+            self.state = logic::State::SelectMove { from, };
+        }
+    }
+
+    fn get_history(&self) -> &[String] {
+
+        &self.history
+    }
+
+    fn resign(&mut self) {
+
+        if !self.state.is_playing() {
+            return;
+        }
+
+        if let Err(e) = self.write(Cts::Resign) {
+            println!("Could not tell the server: {}", e);
+        }
+        println!("You resigned");
+        self.stop_clock();
+        self.state = logic::State::Resigned { winner: self.player.other(), };
+    }
+
+    /// The same message offers a draw and accepts the server's offer
+    fn offer_draw(&mut self) {
+
+        if !self.state.is_playing() || self.draw_offer == Some(self.player) {
+            return;
+        }
+
+        if let Err(e) = self.write(Cts::Draw) {
+            self.disconnect(e);
+            return;
+        }
+
+        if self.draw_offer.is_some() {
+            println!("You accepted the draw");
+            self.end_by_agreement();
+        } else {
+            println!("You offered a draw");
+            self.draw_offer = Some(self.player);
+        }
+    }
+
+    /// The protocol can't decline, the server sees the offer lapse when
+    /// we move
+    fn decline_draw(&mut self) {
+
+        if self.draw_offer == Some(self.player.other()) {
+            self.draw_offer = None;
+        }
+    }
+
+    fn get_draw_offer(&self) -> Option<logic::Player> {
+
+        self.draw_offer
+    }
+
+    /// Sent as a move that goes nowhere, which also agrees to the
+    /// server's request
+    fn request_takeback(&mut self) {
+
+        if !self.state.is_playing()
+            || matches!(self.state, logic::State::ResponsePending)
+            || self.takeback == Some(self.player)
+        {
+            return;
+        }
+
+        if !self.extensions.takeback {
+            println!("The server can't take back moves");
+            return;
+        }
+
+        let agreeing = self.takeback.is_some();
+        if !agreeing && takeback::plies(self.player, self.side_to_move()) > self.history.len() {
+            println!("No move to take back");
+            return;
+        }
+
+        if let Err(e) = self.write(Cts::Move(takeback::request())) {
+            self.disconnect(e);
+            return;
+        }
+
+        // Our request stays until the server answers
+        if agreeing {
+            println!("You agreed to take back");
+        } else {
+            println!("You asked to take back your move");
+            self.takeback = Some(self.player);
+        }
+    }
+
+    /// Like draws, the server sees the request lapse when we move
+    fn decline_takeback(&mut self) {
+
+        if self.takeback == Some(self.player.other()) {
+            self.takeback = None;
+        }
+    }
+
+    fn get_takeback_request(&self) -> Option<logic::Player> {
+
+        self.takeback
+    }
+
+    fn get_clock(&self, player: logic::Player) -> Option<Duration> {
+
+        self.clock.as_ref().map(|clock| clock.remaining(player))
+    }
+
+    fn get_fen(&self) -> String {
+
+        let to_move = self.side_to_move();
+        let fullmove = self.history.len() as u32 / 2 + 1;
+        fen::write(&fen::Position::from_board(logic_board(&self.board), to_move, fullmove))
+    }
+
+    fn get_start_position(&self) -> Option<fen::Position> {
+
+        self.start
+    }
+
+    fn reconnect(&mut self) {
+
+        if !matches!(self.state, logic::State::Disconnected { .. }) {
+            return;
+        }
+
+        // Never got in, so start over
+        self.state = match self.resume {
+            logic::State::Connecting { addr, .. } => logic::State::Connecting { addr, attempt: 1, },
+            _ => logic::State::Reconnecting,
+        };
+        self.start_attempt();
     }
 
     fn update(&mut self) {
 
-        match self.state {
+        let stc = match self.state {
+            logic::State::ResponsePending
+            | logic::State::OpponentTurn
+            | logic::State::SelectPiece
+            | logic::State::SelectMove { .. }
+            | logic::State::SelectPromotion { .. } => self.receive(),
+            logic::State::Reconnecting => {
+                self.try_reconnect();
+                return;
+            },
+            logic::State::Connecting { .. } => {
+                self.try_connect();
+                return;
+            },
+            _ => return,
+        };
+        let stc = match stc.and_then(|stc| self.handle_any_turn(stc)) {
+            Some(stc) => stc,
+            None => return,
+        };
 
-            logic::State::ResponsePending => {
-
-                if let Some(stc) = self.tcp_handler.read() {
-
-                    match stc {
-                        Stc::State {
-                            board,
-                            joever,
-                            ..
-                        } => {
-                            self.board = board.clone();
-                            match joever {
-                                protocol::Joever::Ongoing => self.state = logic::State::OpponentTurn,
-                                protocol::Joever::White => 
-                                    self.state = logic::State::CheckMate(logic::Player::White),
-                                protocol::Joever::Black => 
-                                    self.state = logic::State::CheckMate(logic::Player::Black),
-                                _ => panic!("Joever not implemented: {:?}", joever),
-                            };
-                        },
-                        Stc::Error {
-                            board,
-                            ..
-                        } => {
-                            self.board = board.clone();
-                            self.state = logic::State::SelectPiece;
-                        },
-                        _ => panic!("ServerToCLient not implemented: {:?}", stc),
-                    }
+        match (self.state, stc) {
+
+            (
+                logic::State::ResponsePending,
+                Stc::State {
+                    board,
+                    moves,
+                    joever,
+                    move_made,
+                },
+            ) => {
+                self.record(move_made, &board, is_win(joever));
+                self.press_clock(self.player, &joever);
+                self.board = board;
+                self.moves = moves;
+
+                // Moving instead of answering turns the offer down
+                if self.draw_offer == Some(self.player.other()) {
+                    self.draw_offer = None;
                 }
+                if self.takeback == Some(self.player.other()) {
+                    self.takeback = None;
+                }
+
+                self.state = self.after_move(joever, logic::State::OpponentTurn);
             },
-            logic::State::OpponentTurn => {
-                
-                if let Some(stc) = self.tcp_handler.read() {
-
-                    match stc {
-
-                        Stc::State {
-                            board,
-                            joever,
-                            ..
-                        } => {
-
-                            self.board = board.clone();
-                            match joever {
-                                protocol::Joever::Ongoing => self.state = logic::State::SelectPiece,
-                                protocol::Joever::White => 
-                                    self.state = logic::State::CheckMate(logic::Player::White),
-                                protocol::Joever::Black => 
-                                    self.state = logic::State::CheckMate(logic::Player::Black),
-                                _ => panic!("Joever not implemented: {:?}", joever),
-                            };
-                        },
-
-                        Stc::Error { .. } => (/* not possible */),
-                        _ => panic!("ServerToClient not implemented: {:?}", stc),
-                    }
+            (
+                logic::State::ResponsePending,
+                Stc::Error {
+                    board,
+                    moves,
+                    ..
+                },
+            ) => {
+                self.board = board;
+                self.moves = moves;
+                self.state = logic::State::SelectPiece;
+            },
+            (
+                logic::State::OpponentTurn,
+                Stc::State {
+                    board,
+                    moves,
+                    joever,
+                    move_made,
+                },
+            ) => {
+                self.record(move_made, &board, is_win(joever));
+                self.press_clock(self.player.other(), &joever);
+                self.board = board;
+                self.moves = moves;
+
+                if self.draw_offer == Some(self.player) {
+                    self.draw_offer = None;
+                }
+                if self.takeback == Some(self.player) {
+                    self.takeback = None;
                 }
+
+                self.state = self.after_move(joever, logic::State::SelectPiece);
             },
-            _ => (/* Other cases handled by other methods*/),
+            (_, stc) => println!("Ignoring unexpected message: {:?}", stc),
         }
     }
 }
 
-fn proto_to_logic(piece: protocol::Piece) -> Option<(logic::Piece, logic::Player)> {
+/// Connects to the server and asks to watch its game, or the one with ID
+/// `game` on a lobby
+pub fn watch(addrs: &[SocketAddr], game: Option<u32>) -> Result<(TcpStream, StcHand), tcp::Error> {
+
+    let stream = open(addrs).map_err(tcp::Error::Io)?;
+    println!("Connected!");
 
-    match piece {
-        protocol::Piece::None        => None,
+    lobby::spectate(&stream, game).map_err(tcp::Error::Io)?;
+    let stch = tcp::read(&stream)?;
+    Ok((stream, stch))
+}
 
-        protocol::Piece::BlackPawn   => Some((logic::Piece::Pawn, logic::Player::Black)),
-        protocol::Piece::BlackRook   => Some((logic::Piece::Rook, logic::Player::Black)),
-        protocol::Piece::BlackKnight => Some((logic::Piece::Knight, logic::Player::Black)),
-        protocol::Piece::BlackBishop => Some((logic::Piece::Bishop, logic::Player::Black)),
-        protocol::Piece::BlackQueen  => Some((logic::Piece::Queen, logic::Player::Black)),
-        protocol::Piece::BlackKing   => Some((logic::Piece::King, logic::Player::Black)),
+/// Connects to the first of `addrs` that answers, or fails like the last
+fn open(addrs: &[SocketAddr]) -> io::Result<TcpStream> {
 
-        protocol::Piece::WhitePawn   => Some((logic::Piece::Pawn, logic::Player::White)),
-        protocol::Piece::WhiteRook   => Some((logic::Piece::Rook, logic::Player::White)),
-        protocol::Piece::WhiteKnight => Some((logic::Piece::Knight, logic::Player::White)),
-        protocol::Piece::WhiteBishop => Some((logic::Piece::Bishop, logic::Player::White)),
-        protocol::Piece::WhiteQueen  => Some((logic::Piece::Queen, logic::Player::White)),
-        protocol::Piece::WhiteKing   => Some((logic::Piece::King, logic::Player::White)),
+    let mut error = io::Error::new(io::ErrorKind::InvalidInput, "no address to connect to");
+    for addr in addrs {
+        match TcpStream::connect_timeout(addr, CONNECT_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => error = e,
+        }
     }
+
+    Err(error)
+}
+
+/// Connects, finds the game if the server is a lobby and performs the
+/// handshake, which blocks until the game starts
+fn establish(
+    addrs: &[SocketAddr],
+    player: logic::Player,
+    entry: lobby::Entry,
+) -> Result<Connection, logic::Disconnect> {
+
+    let stream = open(addrs).map_err(|e| {
+        println!("Could not connect: {}", e);
+        logic::Disconnect::Broken
+    })?;
+    println!("Connected!");
+
+    // Joining a game on a lobby decides the colour
+    let player = match lobby::enter(&stream, entry) {
+        Ok(colour) => colour.unwrap_or(player),
+        Err(e) if e.kind() == io::ErrorKind::Other => {
+            println!("Lobby turned us away: {}", e);
+            return Err(logic::Disconnect::Refused);
+        },
+        Err(e) => {
+            println!("Could not enter lobby: {}", e);
+            return Err(logic::Disconnect::Broken);
+        },
+    };
+
+    let stch = handshake(&stream, player).map_err(|e| {
+        println!("Handshake failed: {}", e);
+        logic::Disconnect::from(e)
+    })?;
+
+    Ok((stream, stch, player))
+}
+
+/// Connects back to a game that went on without us. The server answers
+/// right away, so unlike `establish` this gives up on a silent one.
+fn rejoin(addrs: &[SocketAddr], player: logic::Player) -> Result<Connection, logic::Disconnect> {
+
+    let stream = open(addrs).map_err(|e| {
+        println!("Could not connect: {}", e);
+        logic::Disconnect::Broken
+    })?;
+    println!("Connected!");
+
+    let stch = stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT)).map_err(tcp::Error::Io)
+        .and_then(|_| handshake(&stream, player))
+        .and_then(|stch| stream.set_read_timeout(None).map(|_| stch).map_err(tcp::Error::Io))
+        .map_err(|e| {
+            println!("Handshake failed: {}", e);
+            logic::Disconnect::from(e)
+        })?;
+
+    Ok((stream, stch, player))
+}
+
+/// Tells the server the colour we play and reads its answer
+pub fn handshake(stream: &TcpStream, player: logic::Player) -> Result<StcHand, tcp::Error> {
+
+    let ctsh = ClientHandshake {
+        server_color: match player {
+            logic::Player::White => protocol::Color::Black,
+            logic::Player::Black => protocol::Color::White,
+        },
+        features: Extensions::ours().to_features(),
+    };
+    tcp::write(stream, ctsh)?;
+
+    tcp::read(stream)
+}
+
+/// A move from the protocol in SAN, `before` and `after` are the boards
+/// around it
+pub fn protocol_san(
+    before: &[[protocol::Piece; 8]; 8],
+    mov: protocol::Move,
+    after: &[[protocol::Piece; 8]; 8],
+    mate: bool,
+) -> Option<String> {
+
+    let from = logic::Square::from_protocol(mov.start_x, mov.start_y)?;
+    let to = logic::Square::from_protocol(mov.end_x, mov.end_y)?;
+    let promotion = proto_to_logic(mov.promotion).map(|(piece, _)| piece);
+
+    let mov = logic::Move { from, to, promotion, };
+    Some(san::san(&logic_board(before), mov, &logic_board(after), mate))
+}
+
+/// Whether the result has a winner, which makes the move mate
+pub fn is_win(joever: protocol::Joever) -> bool {
+
+    matches!(joever, protocol::Joever::White | protocol::Joever::Black)
+}
+
+/// The handshake only tells whose turn it is through the owner of the
+/// legal moves, without them white is assumed to start
+pub fn to_move(board: &[[protocol::Piece; 8]; 8], moves: &[protocol::Move]) -> logic::Player {
+
+    moves.first()
+        .and_then(|mov| logic::Square::from_protocol(mov.start_x, mov.start_y))
+        .and_then(|at| san::piece_at(&logic_board(board), at))
+        .map_or(logic::Player::White, |(_, player)| player)
+}
+
+pub fn logic_board(board: &[[protocol::Piece; 8]; 8]) -> logic::Board {
+
+    // Both are indexed by rank, then file
+    board.map(|row| row.map(proto_to_logic))
 }
@@ -0,0 +1,236 @@
+use chess_network_protocol as protocol;
+
+use crate::logic::Player;
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::{ Duration, Instant, };
+
+/// The server ends games without a move only when a clock runs out, with
+/// this as the message
+pub const OUT_OF_TIME: &str = "Out of time";
+
+// The protocol has no time controls, so they are sent as an extra feature
+const FEATURE_PREFIX: &str = "time ";
+
+/// Written like "5+3", minutes and then seconds added after each move,
+/// optionally followed by "d2" for a 2 second delay before the clock runs
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct TimeControl {
+    pub base:      Duration,
+    pub increment: Duration,
+    pub delay:     Duration,
+}
+
+impl FromStr for TimeControl {
+
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+
+        let (s, delay) = match s.split_once('d') {
+            Some((s, delay)) => (s, seconds(delay)?),
+            None => (s, Duration::ZERO),
+        };
+        let (base, increment) = match s.split_once('+') {
+            Some((base, increment)) => (base, seconds(increment)?),
+            None => (s, Duration::ZERO),
+        };
+        let base = seconds(base)? * 60;
+
+        if base.is_zero() {
+            return Err(());
+        }
+
+        Ok(Self { base, increment, delay, })
+    }
+}
+
+impl fmt::Display for TimeControl {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+
+        write!(f, "{}+{}", self.base.as_secs_f64() / 60., self.increment.as_secs_f64())?;
+        if !self.delay.is_zero() {
+            write!(f, "d{}", self.delay.as_secs_f64())?;
+        }
+
+        Ok(())
+    }
+}
+
+fn seconds(s: &str) -> Result<Duration, ()> {
+
+    match s.parse::<f64>() {
+        Ok(secs) if secs >= 0. && secs.is_finite() => Ok(Duration::from_secs_f64(secs)),
+        _ => Err(()),
+    }
+}
+
+pub struct Clock {
+    control:   TimeControl,
+    // Indexed by `index`
+    remaining: [Duration; 2],
+    // Whose clock runs and since when
+    running:   Option<(Player, Instant)>,
+}
+
+impl Clock {
+
+    pub fn new(control: TimeControl) -> Self {
+
+        Self {
+            control,
+            remaining: [control.base; 2],
+            running: None,
+        }
+    }
+
+    pub fn control(&self) -> TimeControl {
+
+        self.control
+    }
+
+    /// Starts the time of `player`
+    pub fn start(&mut self, player: Player) {
+
+        self.running = Some((player, Instant::now()));
+    }
+
+    /// Ends the turn of `player`, adds the increment and starts the time
+    /// of the opponent
+    pub fn press(&mut self, player: Player) {
+
+        let left = self.remaining(player);
+        self.remaining[index(player)] = left + self.control.increment;
+        self.start(player.other());
+    }
+
+    /// Stops both clocks when the game is over
+    pub fn stop(&mut self) {
+
+        if let Some((player, _)) = self.running {
+            self.remaining[index(player)] = self.remaining(player);
+        }
+        self.running = None;
+    }
+
+    pub fn remaining(&self, player: Player) -> Duration {
+
+        let left = self.remaining[index(player)];
+        match self.running {
+            Some((running, since)) if running == player => {
+                let used = since.elapsed().saturating_sub(self.control.delay);
+                left.saturating_sub(used)
+            },
+            _ => left,
+        }
+    }
+
+    /// The player who ran out of time, if any
+    pub fn flagged(&self) -> Option<Player> {
+
+        match self.running {
+            Some((player, _)) if self.remaining(player).is_zero() => Some(player),
+            _ => None,
+        }
+    }
+}
+
+fn index(player: Player) -> usize {
+
+    match player {
+        Player::White => 0,
+        Player::Black => 1,
+    }
+}
+
+pub fn to_feature(control: TimeControl) -> protocol::Features {
+
+    protocol::Features::Other(format!("{}{}", FEATURE_PREFIX, control))
+}
+
+pub fn from_features(features: &[protocol::Features]) -> Option<TimeControl> {
+
+    features.iter().find_map(|feature| match feature {
+        protocol::Features::Other(s) => s.strip_prefix(FEATURE_PREFIX)?.parse().ok(),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    use std::thread;
+
+    fn control(base: Duration, increment: Duration, delay: Duration) -> TimeControl {
+
+        TimeControl { base, increment, delay, }
+    }
+
+    #[test]
+    fn time_controls() {
+
+        let blitz: TimeControl = "5+3".parse().unwrap();
+        assert!(blitz == control(Duration::from_secs(300), Duration::from_secs(3), Duration::ZERO));
+
+        let delayed: TimeControl = "0.5d2".parse().unwrap();
+        assert!(delayed == control(Duration::from_secs(30), Duration::ZERO, Duration::from_secs(2)));
+
+        for bad in ["", "0+3", "-1", "5+x", "5+3d", "inf"] {
+            assert!(bad.parse::<TimeControl>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn features_carry_the_time_control() {
+
+        let control: TimeControl = "3+2d1".parse().unwrap();
+        let features = [protocol::Features::PossibleMoveGeneration, to_feature(control)];
+
+        assert!(from_features(&features) == Some(control));
+        assert!(from_features(&features[..1]).is_none());
+    }
+
+    #[test]
+    fn pressing_adds_the_increment() {
+
+        let base = Duration::from_secs(60);
+        let mut clock = Clock::new(control(base, Duration::from_secs(5), Duration::ZERO));
+        clock.start(Player::White);
+        clock.press(Player::White);
+
+        assert!(clock.remaining(Player::White) > base);
+        assert!(clock.remaining(Player::Black) <= base);
+    }
+
+    #[test]
+    fn the_delay_passes_before_the_clock_runs() {
+
+        let base = Duration::from_secs(60);
+        let mut clock = Clock::new(control(base, Duration::ZERO, Duration::from_secs(10)));
+        clock.start(Player::White);
+        thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(clock.remaining(Player::White), base);
+    }
+
+    #[test]
+    fn flags_fall_on_the_running_clock() {
+
+        let mut clock = Clock::new(control(Duration::from_millis(10), Duration::ZERO, Duration::ZERO));
+        clock.start(Player::Black);
+        assert_eq!(clock.flagged(), None);
+
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(clock.flagged(), Some(Player::Black));
+        assert_eq!(clock.remaining(Player::White), Duration::from_millis(10));
+
+        // Stopping keeps the time left, but no clock runs to fall
+        clock.stop();
+        assert!(clock.remaining(Player::Black).is_zero());
+        assert_eq!(clock.flagged(), None);
+    }
+}
@@ -0,0 +1,97 @@
+use crate::logic;
+use crate::lobby;
+use crate::headless;
+use crate::extensions::Extensions;
+
+use std::io;
+use std::net::{ SocketAddr, TcpListener, TcpStream, };
+use std::sync::mpsc::{ self, Receiver, Sender, };
+use std::thread;
+use std::time::Duration;
+
+// How long a client may take to say what it wants
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A new connection that said what it wants
+pub enum Arrival {
+    /// Sent the handshake, to play `player`
+    Player { stream: TcpStream, addr: SocketAddr, player: logic::Player, extensions: Extensions, },
+    /// Asked to watch, the handshake is ours to send
+    Spectator { stream: TcpStream, addr: SocketAddr, },
+}
+
+/// Takes connections without ever blocking. What they open with is read
+/// on a thread of their own, so a slow client can't hold up the game.
+pub struct Door {
+    // None when someone else listens and sends the arrivals on
+    listener: Option<TcpListener>,
+    sender:   Sender<Arrival>,
+    arrivals: Receiver<Arrival>,
+}
+
+impl Door {
+
+    pub fn new(listener: TcpListener) -> io::Result<Self> {
+
+        listener.set_nonblocking(true)?;
+        let (sender, arrivals) = mpsc::channel();
+
+        Ok(Self { listener: Some(listener), sender, arrivals, })
+    }
+
+    /// A door for a game behind a lobby, which lets in whatever is sent
+    /// through the returned sender
+    pub fn inner() -> (Self, Sender<Arrival>) {
+
+        let (sender, arrivals) = mpsc::channel();
+
+        (Self { listener: None, sender: sender.clone(), arrivals, }, sender)
+    }
+
+    /// The next connection that got as far as saying what it wants, if
+    /// there is one
+    pub fn poll(&self) -> Option<Arrival> {
+
+        while let Some(Ok((stream, addr))) = self.listener.as_ref().map(TcpListener::accept) {
+            println!("Connected! ({})", addr);
+            let sender = self.sender.clone();
+            thread::spawn(move || {
+                if let Some(arrival) = greet(stream, addr) {
+                    // Nobody is listening if the game ended meanwhile
+                    let _ = sender.send(arrival);
+                }
+            });
+        }
+
+        self.arrivals.try_recv().ok()
+    }
+}
+
+/// Reads the handshake or request a new connection opens with
+fn greet(stream: TcpStream, addr: SocketAddr) -> Option<Arrival> {
+
+    // Accepted streams may take after the non-blocking listener
+    let request = stream.set_nonblocking(false)
+        .and_then(|_| stream.set_read_timeout(Some(REQUEST_TIMEOUT)))
+        .and_then(|_| lobby::read_request(&stream));
+
+    match request {
+        Ok(None) => match headless::read_handshake(&stream) {
+            Ok((player, extensions)) => Some(Arrival::Player { stream, addr, player, extensions, }),
+            Err(e) => {
+                println!("Handshake with {} failed: {}", addr, e);
+                None
+            },
+        },
+        Ok(Some(request)) if request == lobby::SPECTATE => Some(Arrival::Spectator { stream, addr, }),
+        Ok(Some(_)) => {
+            println!("{} sent an unknown request", addr);
+            lobby::refuse(&stream, "Unknown request");
+            None
+        },
+        Err(e) => {
+            println!("Could not read from {}: {}", addr, e);
+            None
+        },
+    }
+}
@@ -0,0 +1,79 @@
+use crate::logic::{ Board, DrawReason, Piece, Square, };
+use crate::san;
+
+/// The server answers a draw offer it turns down with an error carrying
+/// this message, the protocol has no message for it. Only clients with
+/// the extension for it are sent one.
+pub const DECLINED: &str = "Draw declined";
+
+/// Plies without a capture or pawn move before the game is drawn
+pub const FIFTY_MOVES: u32 = 100;
+
+/// How often a position has to come up before the game is drawn
+pub const REPETITIONS: usize = 3;
+
+/// Whether neither side can mate any more: bare kings, a lone minor
+/// piece, or only bishops that all run on the same colour
+pub fn insufficient_material(board: &Board) -> bool {
+
+    let mut knights = 0;
+    let mut bishop_colours = Vec::new();
+
+    for at in Square::all() {
+        match san::piece_at(board, at) {
+            None | Some((Piece::King, _)) => (),
+            Some((Piece::Knight, _)) => knights += 1,
+            Some((Piece::Bishop, _)) => bishop_colours.push((at.rank + at.file) % 2),
+            Some(_) => return false,
+        }
+    }
+
+    match (knights, bishop_colours.as_slice()) {
+        (0, []) | (1, []) | (0, [_]) => true,
+        (0, [first, rest @ ..]) => rest.iter().all(|colour| colour == first),
+        _ => false,
+    }
+}
+
+/// Plies since the last capture or pawn move, read from the SAN history
+/// of a game that started from the usual position
+pub fn quiet_plies(history: &[String]) -> u32 {
+
+    let quiet = history.iter()
+        .rev()
+        // Pawn moves are the only ones starting with a file
+        .take_while(|san| !san.contains('x') && !san.starts_with(|c: char| c.is_ascii_lowercase()))
+        .count();
+
+    quiet as u32
+}
+
+/// Why a server drew the game, it doesn't say. `positions` are the
+/// boards seen so far, ending with `board`, and `no_moves` whether the
+/// player to move has no legal moves.
+pub fn guess_reason(
+    board: &Board,
+    positions: &[Board],
+    history: &[String],
+    no_moves: bool,
+) -> DrawReason {
+
+    // Every other position has the same player to move
+    let repetitions = positions.iter()
+        .rev()
+        .step_by(2)
+        .filter(|&seen| seen == board)
+        .count();
+
+    if insufficient_material(board) {
+        DrawReason::InsufficientMaterial
+    } else if repetitions >= REPETITIONS {
+        DrawReason::Repetition
+    } else if quiet_plies(history) >= FIFTY_MOVES {
+        DrawReason::FiftyMoves
+    } else if no_moves {
+        DrawReason::Stalemate
+    } else {
+        DrawReason::Declared
+    }
+}
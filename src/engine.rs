@@ -0,0 +1,576 @@
+use crate::logic;
+use crate::game::Game;
+use crate::fen;
+use crate::takeback;
+
+use std::io::{ BufRead, BufReader, Write, };
+use std::process::{ self, Child, ChildStdin, Command, Stdio, };
+use std::sync::mpsc::{ self, Receiver, RecvTimeoutError, };
+use std::thread;
+use std::time::{ Duration, Instant, };
+
+// Used when neither limit is given, engines would think forever otherwise
+const DEFAULT_MOVETIME: u64 = 1000;
+// How long the engine may take to answer each setup command
+const SETUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long the engine may think about each move
+#[derive(Clone, Copy, Default)]
+pub struct Limits {
+    /// In milliseconds
+    pub movetime: Option<u64>,
+    pub depth:    Option<u32>,
+}
+
+/// Plays against a UCI engine running as a child process
+pub struct Engine {
+
+    game: Game,
+    state: logic::State,
+    player: logic::Player,
+    limits: Limits,
+    // Moves played so far, in UCI notation
+    moves: Vec<String>,
+    // The same moves in SAN
+    history: Vec<String>,
+    // The game before each move, for takebacks
+    undo: Vec<Game>,
+    // Answers still owed for positions that were taken back
+    stale: usize,
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+}
+
+impl Engine {
+
+    pub fn new(path: String, limits: Limits) -> logic::Layer {
+
+        match Self::start(path, limits) {
+            Ok(engine) => Box::new(engine),
+            Err(e) => {
+                println!("Could not start engine: {}", e);
+                process::exit(1);
+            },
+        }
+    }
+
+    /// Starts the engine and waits until it's ready for a game
+    fn start(path: String, limits: Limits) -> Result<Self, String> {
+
+        println!("Starting engine {}", path);
+        let mut child = Command::new(&path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| e.to_string())?;
+
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+
+        // Engine output is read on its own thread so update never blocks
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                match line {
+                    Ok(line) => if sender.send(line).is_err() { return },
+                    Err(_) => return,
+                }
+            }
+        });
+
+        let mut engine = Self {
+            game: Game::new(),
+            state: logic::State::SelectPiece,
+            player: logic::Player::White,
+            limits,
+            moves: Vec::new(),
+            history: Vec::new(),
+            undo: Vec::new(),
+            stale: 0,
+            child,
+            stdin,
+            lines,
+        };
+
+        engine.send("uci");
+        engine.wait_for("uciok")?;
+        engine.send("ucinewgame");
+        engine.send("isready");
+        engine.wait_for("readyok")?;
+        println!("Engine ready!");
+
+        Ok(engine)
+    }
+
+    fn send(&mut self, command: &str) {
+
+        if writeln!(self.stdin, "{}", command).is_err() {
+            println!("Engine stopped listening");
+            self.state = logic::State::Disconnected { reason: logic::Disconnect::Closed, };
+        }
+    }
+
+    /// Blocks until the engine prints `reply`, only used during setup
+    fn wait_for(&mut self, reply: &str) -> Result<(), String> {
+
+        let deadline = Instant::now() + SETUP_TIMEOUT;
+        loop {
+            match self.lines.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(line) if line.trim() == reply => return Ok(()),
+                Ok(_) => (),
+                Err(RecvTimeoutError::Timeout) => return Err(format!("no {} in time", reply)),
+                Err(RecvTimeoutError::Disconnected) => return Err(format!("exited before sending {}", reply)),
+            }
+        }
+    }
+
+    /// Asks the engine for its reply to the current position
+    fn go(&mut self) {
+
+        let position = if self.moves.is_empty() {
+            "position startpos".to_string()
+        } else {
+            format!("position startpos moves {}", self.moves.join(" "))
+        };
+        self.send(&position);
+
+        let mut go = "go".to_string();
+        if let Some(depth) = self.limits.depth {
+            go += &format!(" depth {}", depth);
+        }
+        let movetime = match self.limits {
+            Limits { movetime: None, depth: None, } => Some(DEFAULT_MOVETIME),
+            Limits { movetime, .. } => movetime,
+        };
+        if let Some(movetime) = movetime {
+            go += &format!(" movetime {}", movetime);
+        }
+        self.send(&go);
+    }
+
+    fn make_move(
+        &mut self,
+        from: logic::Square,
+        dst: logic::Square,
+        promotion: Option<logic::Piece>,
+    ) {
+
+        let mov = logic::Move { from, to: dst, promotion, };
+        let before = self.game.clone();
+        match self.game.play(mov) {
+            Some(san) => {
+                self.history.push(san);
+                self.undo.push(before);
+            },
+            None => {
+                self.state = logic::State::SelectPiece;
+                return;
+            },
+        }
+        self.moves.push(uci_move(from, dst, promotion));
+
+        match self.game.outcome() {
+            Some(state) => self.state = state,
+            None => {
+                self.state = logic::State::OpponentTurn;
+                self.go();
+            },
+        }
+    }
+
+    fn engine_move(&mut self, mov: &str) {
+
+        let (from, dst, promotion) = match parse_uci_move(mov) {
+            Some(mov) => mov,
+            None => {
+                // "(none)" or "0000" when the engine has no moves left
+                println!("Engine has no move: {}", mov);
+                self.state = logic::State::CheckMate(self.player);
+                return;
+            },
+        };
+
+        // Engines leave out the promotion when it's obvious
+        let promotion = match promotion {
+            None if self.game.promotes(from, dst) => Some(logic::Piece::Queen),
+            promotion => promotion,
+        };
+
+        let before = self.game.clone();
+        let san = match self.game.play(logic::Move { from, to: dst, promotion, }) {
+            Some(san) => san,
+            None => {
+                println!("Engine played illegal move {}", mov);
+                self.state = logic::State::Disconnected { reason: logic::Disconnect::Invalid, };
+                return;
+            },
+        };
+        self.history.push(san);
+        self.undo.push(before);
+        self.moves.push(uci_move(from, dst, promotion));
+
+        self.state = self.game.outcome().unwrap_or(logic::State::SelectPiece);
+    }
+}
+
+impl Drop for Engine {
+
+    fn drop(&mut self) {
+
+        let _ = writeln!(self.stdin, "quit");
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl logic::Interface for Engine {
+
+    fn get_state(&self) -> logic::State {
+
+        self.state
+    }
+
+    fn get_player(&self) -> Option<logic::Player> {
+
+        Some(self.player)
+    }
+
+    fn update(&mut self) {
+
+        if !matches!(self.state, logic::State::OpponentTurn) {
+            return;
+        }
+
+        loop {
+            match self.lines.try_recv() {
+                Ok(line) => {
+                    let mut words = line.split_whitespace();
+                    if words.next() == Some("bestmove") {
+                        if self.stale > 0 {
+                            self.stale -= 1;
+                            continue;
+                        }
+                        self.engine_move(words.next().unwrap_or(""));
+                        return;
+                    }
+                },
+                Err(mpsc::TryRecvError::Empty) => return,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    println!("Engine exited");
+                    self.state = logic::State::Disconnected { reason: logic::Disconnect::Closed, };
+                    return;
+                },
+            }
+        }
+    }
+
+    fn get_piece_at(&self, at: logic::Square) -> Option<(logic::Piece, logic::Player)> {
+
+        self.game.piece_at(at)
+    }
+
+    fn get_destinations(&self, from: logic::Square) -> Vec<logic::Square> {
+
+        self.game.destinations(from)
+    }
+
+    fn select_piece(&mut self, at: logic::Square) {
+
+        if !matches!(self.state, logic::State::SelectPiece) {
+            return;
+        }
+
+        // Must be our piece
+        match self.game.piece_at(at) {
+            Some((_, player)) if player == self.player => (),
+            _ => return,
+        }
+
+        self.state = logic::State::SelectMove { from: at, };
+    }
+
+    fn play_move(&mut self, dst: logic::Square) {
+
+        let from = match self.state {
+            logic::State::SelectMove { from } => from,
+            _ => return,
+        };
+
+        // Pawns reaching the last rank need a promotion first
+        if self.game.promotes(from, dst) {
+            self.state = logic::State::SelectPromotion { from, at: dst, };
+            return;
+        }
+
+        self.make_move(from, dst, None);
+    }
+
+    fn select_promotion(&mut self, piece: logic::Piece) {
+
+        let (from, at) = match self.state {
+            logic::State::SelectPromotion { from, at } => (from, at),
+            _ => return,
+        };
+
+        self.make_move(from, at, Some(piece));
+    }
+
+    fn cancel_promotion(&mut self) {
+
+        if let logic::State::SelectPromotion { from, .. } = self.state {
+            self.state = logic::State::SelectMove { from, };
+        }
+    }
+
+    fn get_history(&self) -> &[String] {
+
+        &self.history
+    }
+
+    fn get_fen(&self) -> String {
+
+        self.game.to_fen()
+    }
+
+    fn get_start_position(&self) -> Option<fen::Position> {
+
+        Some(self.game.start_position())
+    }
+
+    fn get_clock(&self, _player: logic::Player) -> Option<Duration> {
+
+        None
+    }
+
+    fn resign(&mut self) {
+
+        if !self.state.is_playing() {
+            return;
+        }
+
+        if matches!(self.state, logic::State::OpponentTurn) {
+            self.send("stop");
+            self.stale += 1;
+        }
+        self.state = logic::State::Resigned { winner: self.player.other(), };
+    }
+
+    /// UCI has no draw offers, engines play on
+    fn offer_draw(&mut self) {
+
+        if self.state.is_playing() {
+            println!("Engine declines the draw");
+        }
+    }
+
+    /// Takes back our last move and the engine's answer to it, even
+    /// after the game ended
+    fn request_takeback(&mut self) {
+
+        if !self.state.is_playing() && !self.state.is_over() {
+            return;
+        }
+
+        let plies = takeback::plies(self.player, self.game.to_move());
+        if plies > self.undo.len() {
+            return;
+        }
+
+        // A stopped engine still answers the position it was given,
+        // that answer is skipped when it arrives
+        if matches!(self.state, logic::State::OpponentTurn) {
+            self.send("stop");
+            self.stale += 1;
+        }
+        let at = self.undo.len() - plies;
+        self.game = self.undo[at].clone();
+        self.undo.truncate(at);
+        self.history.truncate(at);
+        self.moves.truncate(at);
+        self.state = logic::State::SelectPiece;
+    }
+}
+
+// From, to and promotion
+type UciMove = (logic::Square, logic::Square, Option<logic::Piece>);
+
+fn uci_move(from: logic::Square, to: logic::Square, promotion: Option<logic::Piece>) -> String {
+
+    let promotion = match promotion {
+        Some(logic::Piece::Queen)  => "q",
+        Some(logic::Piece::Rook)   => "r",
+        Some(logic::Piece::Bishop) => "b",
+        Some(logic::Piece::Knight) => "n",
+        _ => "",
+    };
+
+    format!("{}{}{}", from, to, promotion)
+}
+
+fn parse_uci_move(s: &str) -> Option<UciMove> {
+
+    let from = logic::Square::from_algebraic(s.get(0..2)?)?;
+    let to = logic::Square::from_algebraic(s.get(2..4)?)?;
+    let promotion = match &s.as_bytes()[4..] {
+        []     => None,
+        [b'q'] => Some(logic::Piece::Queen),
+        [b'r'] => Some(logic::Piece::Rook),
+        [b'b'] => Some(logic::Piece::Bishop),
+        [b'n'] => Some(logic::Piece::Knight),
+        _ => return None,
+    };
+
+    Some((from, to, promotion))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::logic::Interface;
+
+    // Echoes every command as an info string and answers with its Reply
+    // option, e7e5 unless set
+    const FAKE_ENGINE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fake_engine.sh");
+
+    fn fake_engine(limits: Limits) -> Engine {
+
+        Engine::start(FAKE_ENGINE.to_string(), limits).expect("fake engine should start")
+    }
+
+    fn square(s: &str) -> logic::Square {
+
+        logic::Square::from_algebraic(s).unwrap()
+    }
+
+    /// The commands the engine was sent up to its next bestmove, and that
+    /// bestmove line
+    fn commands_until_reply(engine: &Engine) -> (Vec<String>, String) {
+
+        let mut commands = Vec::new();
+        loop {
+            let line = engine.lines.recv_timeout(Duration::from_secs(5)).expect("engine should answer");
+            match line.strip_prefix("info string ") {
+                Some(command) => commands.push(command.to_string()),
+                None if line.starts_with("bestmove") => return (commands, line),
+                None => (),
+            }
+        }
+    }
+
+    /// Plays e2e4 and updates until the engine has answered it
+    fn play_e4(engine: &mut Engine) {
+
+        engine.make_move(square("e2"), square("e4"), None);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while matches!(engine.state, logic::State::OpponentTurn) && Instant::now() < deadline {
+            engine.update();
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn handshake() {
+
+        let engine = fake_engine(Limits::default());
+        assert!(matches!(engine.state, logic::State::SelectPiece));
+        assert!(Engine::start("/nonexistent/engine".to_string(), Limits::default()).is_err());
+    }
+
+    #[test]
+    fn position_and_default_movetime() {
+
+        let mut engine = fake_engine(Limits::default());
+        engine.make_move(square("e2"), square("e4"), None);
+
+        let (commands, reply) = commands_until_reply(&engine);
+        assert_eq!(commands, ["position startpos moves e2e4", "go movetime 1000"]);
+        assert_eq!(reply, "bestmove e7e5");
+    }
+
+    #[test]
+    fn go_limits() {
+
+        let mut engine = fake_engine(Limits { movetime: None, depth: Some(5), });
+        engine.make_move(square("e2"), square("e4"), None);
+        assert_eq!(commands_until_reply(&engine).0[1], "go depth 5");
+
+        let mut engine = fake_engine(Limits { movetime: Some(200), depth: Some(5), });
+        engine.make_move(square("d2"), square("d4"), None);
+        assert_eq!(commands_until_reply(&engine).0[1], "go depth 5 movetime 200");
+
+        let mut engine = fake_engine(Limits { movetime: Some(200), depth: None, });
+        engine.make_move(square("d2"), square("d4"), None);
+        assert_eq!(commands_until_reply(&engine).0[1], "go movetime 200");
+    }
+
+    #[test]
+    fn moves_are_sent_in_order() {
+
+        let mut engine = fake_engine(Limits::default());
+        play_e4(&mut engine);
+        engine.make_move(square("g1"), square("f3"), None);
+
+        let (commands, _) = commands_until_reply(&engine);
+        assert_eq!(commands[0], "position startpos moves e2e4 e7e5 g1f3");
+    }
+
+    #[test]
+    fn engine_reply() {
+
+        let mut engine = fake_engine(Limits::default());
+        play_e4(&mut engine);
+
+        assert!(matches!(engine.state, logic::State::SelectPiece));
+        assert_eq!(engine.history, ["e4", "e5"]);
+        assert_eq!(engine.moves, ["e2e4", "e7e5"]);
+        assert_eq!(engine.game.piece_at(square("e5")), Some((logic::Piece::Pawn, logic::Player::Black)));
+    }
+
+    #[test]
+    fn illegal_reply() {
+
+        let mut engine = fake_engine(Limits::default());
+        engine.send("setoption name Reply value e2e4");
+        play_e4(&mut engine);
+
+        assert!(matches!(engine.state, logic::State::Disconnected { reason: logic::Disconnect::Invalid, }));
+        assert_eq!(engine.history, ["e4"]);
+    }
+
+    #[test]
+    fn engine_exits() {
+
+        let mut engine = fake_engine(Limits::default());
+        engine.send("setoption name Reply value exit");
+        play_e4(&mut engine);
+
+        assert!(matches!(engine.state, logic::State::Disconnected { reason: logic::Disconnect::Closed, }));
+    }
+
+    #[test]
+    fn parse_moves() {
+
+        assert_eq!(parse_uci_move("e2e4"), Some((square("e2"), square("e4"), None)));
+        assert_eq!(parse_uci_move("e7e8q"), Some((square("e7"), square("e8"), Some(logic::Piece::Queen))));
+        assert_eq!(parse_uci_move("a2a1n"), Some((square("a2"), square("a1"), Some(logic::Piece::Knight))));
+        assert_eq!(parse_uci_move("b7b8r"), Some((square("b7"), square("b8"), Some(logic::Piece::Rook))));
+        assert_eq!(parse_uci_move("c7c8b"), Some((square("c7"), square("c8"), Some(logic::Piece::Bishop))));
+
+        assert_eq!(parse_uci_move("0000"), None);
+        assert_eq!(parse_uci_move("(none)"), None);
+        assert_eq!(parse_uci_move("e7e8k"), None);
+        assert_eq!(parse_uci_move("e2e"), None);
+        assert_eq!(parse_uci_move("e2i4"), None);
+        assert_eq!(parse_uci_move(""), None);
+    }
+
+    #[test]
+    fn write_moves() {
+
+        assert_eq!(uci_move(square("e2"), square("e4"), None), "e2e4");
+        assert_eq!(uci_move(square("e7"), square("e8"), Some(logic::Piece::Queen)), "e7e8q");
+        assert_eq!(uci_move(square("a2"), square("a1"), Some(logic::Piece::Knight)), "a2a1n");
+    }
+}
@@ -0,0 +1,91 @@
+use chess_network_protocol::{ self as protocol, Features, };
+
+use serde::{ Deserialize, Serialize, };
+
+// Extensions are named in the handshake like any other feature, the way
+// the time control is. Peers that don't name one are never sent its
+// messages.
+const TAKEBACK: &str = "takeback";
+const DRAW_DECLINED: &str = "draw declined";
+
+/// Which of our additions to the protocol a peer understands
+#[derive(Clone, Copy, Default)]
+pub struct Extensions {
+    /// Takeback requests and the answers to them
+    pub takeback: bool,
+    /// Being told a draw offer was turned down
+    pub draw_declined: bool,
+}
+
+impl Extensions {
+
+    /// Everything this program understands
+    pub fn ours() -> Self {
+
+        Self { takeback: true, draw_declined: true, }
+    }
+
+    pub fn from_features(features: &[Features]) -> Self {
+
+        let named = |name: &str| features.iter()
+            .any(|feature| matches!(feature, Features::Other(s) if s == name));
+
+        Self {
+            takeback: named(TAKEBACK),
+            draw_declined: named(DRAW_DECLINED),
+        }
+    }
+
+    pub fn to_features(self) -> Vec<Features> {
+
+        let mut features = Vec::new();
+        if self.takeback {
+            features.push(Features::Other(TAKEBACK.to_string()));
+        }
+        if self.draw_declined {
+            features.push(Features::Other(DRAW_DECLINED.to_string()));
+        }
+
+        features
+    }
+}
+
+/// The protocol's client handshake with features like the server's.
+/// Servers that don't know the field skip it, and clients that don't
+/// send it understand nothing extra.
+#[derive(Serialize, Deserialize)]
+pub struct ClientHandshake {
+    pub server_color: protocol::Color,
+    #[serde(default)]
+    pub features: Vec<Features>,
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn features_name_our_extensions() {
+
+        let ours = Extensions::from_features(&Extensions::ours().to_features());
+        assert!(ours.takeback && ours.draw_declined);
+
+        let takeback = Extensions { takeback: true, draw_declined: false, };
+        let read = Extensions::from_features(&takeback.to_features());
+        assert!(read.takeback && !read.draw_declined);
+
+        assert!(!Extensions::from_features(&[Features::PossibleMoveGeneration]).takeback);
+    }
+
+    #[test]
+    fn plain_handshakes_understand_nothing_extra() {
+
+        let plain = protocol::ClientToServerHandshake { server_color: protocol::Color::White, };
+        let json = serde_json::to_string(&plain).unwrap();
+        let read: ClientHandshake = serde_json::from_str(&json).unwrap();
+
+        let extensions = Extensions::from_features(&read.features);
+        assert!(!extensions.takeback && !extensions.draw_declined);
+    }
+}
@@ -0,0 +1,405 @@
+use crate::logic::{ Board, Piece, Player, Square, };
+use crate::san;
+
+use std::fmt;
+
+/// The usual starting position
+pub const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+/// Everything a FEN string describes
+#[derive(Clone, Copy)]
+pub struct Position {
+    pub board:      Board,
+    pub to_move:    Player,
+    pub castling:   Castling,
+    /// The square a pawn skipped over on the last move
+    pub en_passant: Option<Square>,
+    /// Moves since the last capture or pawn move
+    pub halfmove:   u32,
+    /// Starts at 1, counts up after each black move
+    pub fullmove:   u32,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct Castling {
+    pub white_king:  bool,
+    pub white_queen: bool,
+    pub black_king:  bool,
+    pub black_queen: bool,
+}
+
+impl Castling {
+
+    pub const ALL: Self = Self {
+        white_king:  true,
+        white_queen: true,
+        black_king:  true,
+        black_queen: true,
+    };
+
+    pub const NONE: Self = Self {
+        white_king:  false,
+        white_queen: false,
+        black_king:  false,
+        black_queen: false,
+    };
+
+    /// King side and queen side rights of `player`
+    pub fn rights(&self, player: Player) -> (bool, bool) {
+
+        match player {
+            Player::White => (self.white_king, self.white_queen),
+            Player::Black => (self.black_king, self.black_queen),
+        }
+    }
+
+    /// Forgets the rights a move from or to `at` spoils, the king's
+    /// square loses both and a corner loses its side
+    pub fn touch(&mut self, at: Square) {
+
+        match (at.rank, at.file) {
+            (0, 4) => (self.white_king, self.white_queen) = (false, false),
+            (7, 4) => (self.black_king, self.black_queen) = (false, false),
+            (0, 0) => self.white_queen = false,
+            (0, 7) => self.white_king = false,
+            (7, 0) => self.black_queen = false,
+            (7, 7) => self.black_king = false,
+            _ => (),
+        }
+    }
+}
+
+impl Position {
+
+    /// A position known only from its board, castling rights are guessed
+    /// from whether kings and rooks are on their starting squares
+    pub fn from_board(board: Board, to_move: Player, fullmove: u32) -> Self {
+
+        let on = |rank, file, piece| {
+            san::piece_at(&board, Square { rank, file, }) == Some(piece)
+        };
+        let white_king = on(0, 4, (Piece::King, Player::White));
+        let black_king = on(7, 4, (Piece::King, Player::Black));
+
+        Self {
+            board,
+            to_move,
+            castling: Castling {
+                white_king:  white_king && on(0, 7, (Piece::Rook, Player::White)),
+                white_queen: white_king && on(0, 0, (Piece::Rook, Player::White)),
+                black_king:  black_king && on(7, 7, (Piece::Rook, Player::Black)),
+                black_queen: black_king && on(7, 0, (Piece::Rook, Player::Black)),
+            },
+            en_passant: None,
+            halfmove: 0,
+            fullmove,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// Not 4 or 6 space separated fields
+    Fields,
+    Board(&'static str),
+    ToMove,
+    Castling,
+    /// A castling right for a king or rook that isn't on its starting square
+    CastlingPieces,
+    EnPassant,
+    Counter,
+}
+
+impl fmt::Display for Error {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+
+        match self {
+            Error::Fields => write!(f, "expected 4 or 6 fields"),
+            Error::Board(reason) => write!(f, "bad board: {}", reason),
+            Error::ToMove => write!(f, "side to move must be w or b"),
+            Error::Castling => write!(f, "castling rights must be - or some of KQkq"),
+            Error::CastlingPieces => write!(f, "castling rights need the king and rook on their starting squares"),
+            Error::EnPassant => write!(f, "bad en passant square"),
+            Error::Counter => write!(f, "bad move counter"),
+        }
+    }
+}
+
+/// Reads a FEN string, the move counters may be left out
+pub fn parse(fen: &str) -> Result<Position, Error> {
+
+    let fields: Vec<_> = fen.split_whitespace().collect();
+    if fields.len() != 4 && fields.len() != 6 {
+        return Err(Error::Fields);
+    }
+
+    let board = parse_board(fields[0])?;
+
+    let to_move = match fields[1] {
+        "w" => Player::White,
+        "b" => Player::Black,
+        _ => return Err(Error::ToMove),
+    };
+
+    let castling = parse_castling(fields[2])?;
+    let possible = Position::from_board(board, to_move, 1).castling;
+    for player in [Player::White, Player::Black] {
+        let (king, queen) = castling.rights(player);
+        let (can_king, can_queen) = possible.rights(player);
+        if (king && !can_king) || (queen && !can_queen) {
+            return Err(Error::CastlingPieces);
+        }
+    }
+
+    // The pawn that skipped the square belongs to whoever just moved
+    let en_passant = match fields[3] {
+        "-" => None,
+        square => match (Square::from_algebraic(square), to_move) {
+            (Some(square), Player::White) if square.rank == 5 => Some(square),
+            (Some(square), Player::Black) if square.rank == 2 => Some(square),
+            _ => return Err(Error::EnPassant),
+        },
+    };
+
+    let (halfmove, fullmove) = match fields.get(4..6) {
+        Some([halfmove, fullmove]) => (
+            halfmove.parse().map_err(|_| Error::Counter)?,
+            fullmove.parse().map_err(|_| Error::Counter)?,
+        ),
+        _ => (0, 1),
+    };
+    if fullmove == 0 {
+        return Err(Error::Counter);
+    }
+
+    Ok(Position { board, to_move, castling, en_passant, halfmove, fullmove, })
+}
+
+pub fn write(position: &Position) -> String {
+
+    let mut ranks = Vec::new();
+    for rank in (0..8).rev() {
+
+        let mut text = String::new();
+        let mut empty = 0;
+        for file in 0..8 {
+            match san::piece_at(&position.board, Square { rank, file, }) {
+                Some(piece) => {
+                    if empty > 0 {
+                        text += &empty.to_string();
+                        empty = 0;
+                    }
+                    text.push(letter(piece));
+                },
+                None => empty += 1,
+            }
+        }
+        if empty > 0 {
+            text += &empty.to_string();
+        }
+
+        ranks.push(text);
+    }
+
+    let to_move = match position.to_move {
+        Player::White => "w",
+        Player::Black => "b",
+    };
+
+    let mut castling = String::new();
+    for (right, c) in [
+        (position.castling.white_king,  'K'),
+        (position.castling.white_queen, 'Q'),
+        (position.castling.black_king,  'k'),
+        (position.castling.black_queen, 'q'),
+    ] {
+        if right {
+            castling.push(c);
+        }
+    }
+    if castling.is_empty() {
+        castling.push('-');
+    }
+
+    let en_passant = position.en_passant.map_or("-".to_string(), Square::to_algebraic);
+
+    format!("{} {} {} {} {} {}",
+        ranks.join("/"),
+        to_move,
+        castling,
+        en_passant,
+        position.halfmove,
+        position.fullmove,
+    )
+}
+
+fn parse_board(text: &str) -> Result<Board, Error> {
+
+    let ranks: Vec<_> = text.split('/').collect();
+    if ranks.len() != 8 {
+        return Err(Error::Board("expected 8 ranks"));
+    }
+
+    let mut board = [[None; 8]; 8];
+
+    // Written from the eighth rank down
+    for (rank, text) in (0..8).rev().zip(ranks) {
+
+        let mut file = 0;
+        for c in text.chars() {
+
+            if file >= 8 {
+                return Err(Error::Board("rank longer than 8 squares"));
+            }
+
+            match c {
+                '1'..='8' => file += c as usize - '0' as usize,
+                _ => {
+                    let piece = san::from_letter(c.to_ascii_uppercase())
+                        .ok_or(Error::Board("unknown piece"))?;
+                    let player = if c.is_ascii_uppercase() { Player::White } else { Player::Black };
+
+                    if piece == Piece::Pawn && (rank == 0 || rank == 7) {
+                        return Err(Error::Board("pawn on the first or last rank"));
+                    }
+
+                    board[rank][file] = Some((piece, player));
+                    file += 1;
+                },
+            }
+        }
+
+        if file != 8 {
+            return Err(Error::Board("rank not 8 squares long"));
+        }
+    }
+
+    for player in [Player::White, Player::Black] {
+        let kings = board.iter()
+            .flatten()
+            .filter(|&&square| square == Some((Piece::King, player)))
+            .count();
+        if kings != 1 {
+            return Err(Error::Board("each side needs exactly one king"));
+        }
+    }
+
+    Ok(board)
+}
+
+fn parse_castling(text: &str) -> Result<Castling, Error> {
+
+    if text == "-" {
+        return Ok(Castling::NONE);
+    }
+
+    let mut castling = Castling::NONE;
+
+    for c in text.chars() {
+        let right = match c {
+            'K' => &mut castling.white_king,
+            'Q' => &mut castling.white_queen,
+            'k' => &mut castling.black_king,
+            'q' => &mut castling.black_queen,
+            _ => return Err(Error::Castling),
+        };
+        // Each right only once
+        if *right {
+            return Err(Error::Castling);
+        }
+        *right = true;
+    }
+
+    Ok(castling)
+}
+
+/// Upper case for white, lower case for black
+fn letter((piece, player): (Piece, Player)) -> char {
+
+    match player {
+        Player::White => san::letter(piece),
+        Player::Black => san::letter(piece).to_ascii_lowercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn round_trip(fen: &str) {
+
+        assert_eq!(write(&parse(fen).unwrap()), fen);
+    }
+
+    #[test]
+    fn start() {
+
+        round_trip(START);
+
+        let start = parse(START).unwrap();
+        assert_eq!(start.to_move, Player::White);
+        assert!(start.castling == Castling::ALL);
+        assert_eq!(start.en_passant, None);
+        assert_eq!((start.halfmove, start.fullmove), (0, 1));
+        assert_eq!(san::piece_at(&start.board, Square::from_algebraic("e1").unwrap()), Some((Piece::King, Player::White)));
+        assert_eq!(san::piece_at(&start.board, Square::from_algebraic("d8").unwrap()), Some((Piece::Queen, Player::Black)));
+    }
+
+    #[test]
+    fn castling() {
+
+        round_trip("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+        round_trip("r3k2r/8/8/8/8/8/8/R3K2R w Kq - 0 1");
+        round_trip("r3k2r/8/8/8/8/8/8/R3K2R b Qk - 0 1");
+        round_trip("r3k2r/8/8/8/8/8/8/R3K2R w - - 0 1");
+
+        let castling = parse("r3k2r/8/8/8/8/8/8/R3K2R w Kq - 0 1").unwrap().castling;
+        assert_eq!(castling.rights(Player::White), (true, false));
+        assert_eq!(castling.rights(Player::Black), (false, true));
+    }
+
+    #[test]
+    fn en_passant() {
+
+        round_trip("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1");
+        round_trip("rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 3");
+
+        let position = parse("rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 3").unwrap();
+        assert_eq!(position.en_passant, Square::from_algebraic("d6"));
+    }
+
+    #[test]
+    fn counters() {
+
+        round_trip("4k3/8/8/8/8/8/8/4K3 b - - 37 112");
+
+        // Both may be left out
+        assert_eq!(write(&parse("4k3/8/8/8/8/8/8/4K3 w - -").unwrap()), "4k3/8/8/8/8/8/8/4K3 w - - 0 1");
+    }
+
+    #[test]
+    fn rejects() {
+
+        assert!(matches!(parse("4k3/8/8/8/8/8/8/4K3 w -"), Err(Error::Fields)));
+        assert!(matches!(parse("4k3/8/8/8/8/8/4K3 w - - 0 1"), Err(Error::Board(_))));
+        assert!(matches!(parse("4k3/8/8/9/8/8/8/4K3 w - - 0 1"), Err(Error::Board(_))));
+        assert!(matches!(parse("4k3/8/8/8p/8/8/8/4K3 w - - 0 1"), Err(Error::Board(_))));
+        assert!(matches!(parse("4k3/8/8/7/8/8/8/4K3 w - - 0 1"), Err(Error::Board(_))));
+        assert!(matches!(parse("4k3/8/8/8/8/8/8/3KK3 w - - 0 1"), Err(Error::Board(_))));
+        assert!(matches!(parse("8/8/8/8/8/8/8/4K3 w - - 0 1"), Err(Error::Board(_))));
+        assert!(matches!(parse("3pk3/8/8/8/8/8/8/4K3 w - - 0 1"), Err(Error::Board(_))));
+        assert!(matches!(parse("4k3/8/8/8/8/8/8/4K2P w - - 0 1"), Err(Error::Board(_))));
+        assert!(matches!(parse("4k3/8/8/8/8/8/8/4K3 x - - 0 1"), Err(Error::ToMove)));
+        assert!(matches!(parse("4k3/8/8/8/8/8/8/4K3 w KK - 0 1"), Err(Error::Castling)));
+        assert!(matches!(parse("4k3/8/8/8/8/8/8/4K3 w X - 0 1"), Err(Error::Castling)));
+        assert!(matches!(parse("4k3/8/8/8/8/8/8/4K3 w K - 0 1"), Err(Error::CastlingPieces)));
+        assert!(matches!(parse("r3k2r/8/8/8/8/8/8/R4K1R w Q - 0 1"), Err(Error::CastlingPieces)));
+        assert!(matches!(parse("r3k3/8/8/8/8/8/8/4K3 b k - 0 1"), Err(Error::CastlingPieces)));
+        assert!(matches!(parse("4k3/8/8/8/8/8/8/4K3 w - e3 0 1"), Err(Error::EnPassant)));
+        assert!(matches!(parse("4k3/8/8/8/8/8/8/4K3 w - e9 0 1"), Err(Error::EnPassant)));
+        assert!(matches!(parse("4k3/8/8/8/8/8/8/4K3 w - - 0 0"), Err(Error::Counter)));
+        assert!(matches!(parse("4k3/8/8/8/8/8/8/4K3 w - - x 1"), Err(Error::Counter)));
+    }
+}
@@ -0,0 +1,700 @@
+use simonsev_chess as backend;
+use chess_network_protocol as protocol;
+
+use crate::logic::{ self, logic_to_proto, proto_to_logic, };
+use crate::fen;
+use crate::san;
+use crate::draw;
+
+/// Wraps the backend with the move validation and conversions the
+/// layers share
+#[derive(Clone)]
+pub struct Game {
+
+    backend: backend::Game,
+    // Kept for FEN, the backend has its own idea of the rest
+    to_move: logic::Player,
+    castling: fen::Castling,
+    en_passant: Option<logic::Square>,
+    halfmove: u32,
+    fullmove: u32,
+    // Whether the last move mated, the backend's answer isn't always right
+    mate: bool,
+    // Where the game started
+    start: fen::Position,
+    // Every position so far, to spot repetitions
+    positions: Vec<PositionKey>,
+}
+
+/// What has to match for positions to count as repeated
+pub type PositionKey = (logic::Board, logic::Player, fen::Castling, Option<logic::Square>);
+
+impl Game {
+
+    pub fn new() -> Self {
+
+        let mut game = Self {
+            backend: backend::Game::new(),
+            to_move: logic::Player::White,
+            castling: fen::Castling::ALL,
+            en_passant: None,
+            halfmove: 0,
+            fullmove: 1,
+            mate: false,
+            start: fen::parse(fen::START).unwrap(),
+            positions: Vec::new(),
+        };
+        game.positions.push(game.position_key());
+
+        game
+    }
+
+    /// Starts from a position set up from FEN
+    pub fn from_position(position: &fen::Position) -> Self {
+
+        let mut backend = backend::Game::new();
+
+        for at in logic::Square::all() {
+            put(&mut backend, at, san::piece_at(&position.board, at));
+        }
+        // The backend can't be told about the en passant square, captures
+        // on it are played here instead, see `capture_en_passant`
+        backend.white_turn = position.to_move == logic::Player::White;
+
+        let mut game = Self {
+            backend,
+            to_move: position.to_move,
+            castling: position.castling,
+            en_passant: position.en_passant,
+            halfmove: position.halfmove,
+            fullmove: position.fullmove,
+            mate: false,
+            start: *position,
+            positions: Vec::new(),
+        };
+        game.positions.push(game.position_key());
+
+        game
+    }
+
+    pub fn to_move(&self) -> logic::Player {
+
+        self.to_move
+    }
+
+    pub fn to_position(&self) -> fen::Position {
+
+        fen::Position {
+            board: self.board(),
+            to_move: self.to_move,
+            castling: self.castling,
+            en_passant: self.en_passant,
+            halfmove: self.halfmove,
+            fullmove: self.fullmove,
+        }
+    }
+
+    pub fn to_fen(&self) -> String {
+
+        fen::write(&self.to_position())
+    }
+
+    /// The position before the first move
+    pub fn start_position(&self) -> fen::Position {
+
+        self.start
+    }
+
+    /// Every position so far, the current one last
+    pub fn positions(&self) -> &[PositionKey] {
+
+        &self.positions
+    }
+
+    /// A copy that only remembers the current position, which keeps
+    /// clones cheap when searching
+    pub fn without_history(&self) -> Self {
+
+        Self {
+            backend: self.backend.clone(),
+            to_move: self.to_move,
+            castling: self.castling,
+            en_passant: self.en_passant,
+            halfmove: self.halfmove,
+            fullmove: self.fullmove,
+            mate: self.mate,
+            start: self.start,
+            positions: self.positions.last().cloned().into_iter().collect(),
+        }
+    }
+
+    /// Plies since the last capture or pawn move
+    pub fn halfmove(&self) -> u32 {
+
+        self.halfmove
+    }
+
+    /// Whether the last move check-mated the opponent
+    pub fn mate(&self) -> bool {
+
+        self.mate
+    }
+
+    /// How the last move ended the game, `None` if it goes on
+    pub fn outcome(&self) -> Option<logic::State> {
+
+        let winner = self.to_move.other();
+        if self.mate() {
+            return Some(logic::State::CheckMate(winner));
+        }
+
+        if self.legal_moves(self.to_move).is_empty() {
+            return Some(if san::in_check(&self.board(), self.to_move) {
+                logic::State::CheckMate(winner)
+            } else {
+                logic::State::Drawn { reason: logic::DrawReason::Stalemate, }
+            });
+        }
+
+        let current = self.positions.last();
+        let repetitions = self.positions.iter().filter(|&key| Some(key) == current).count();
+
+        let reason = if draw::insufficient_material(&self.board()) {
+            logic::DrawReason::InsufficientMaterial
+        } else if repetitions >= draw::REPETITIONS {
+            logic::DrawReason::Repetition
+        } else if self.halfmove >= draw::FIFTY_MOVES {
+            logic::DrawReason::FiftyMoves
+        } else {
+            return None;
+        };
+
+        Some(logic::State::Drawn { reason, })
+    }
+
+    fn position_key(&self) -> PositionKey {
+
+        (self.board(), self.to_move, self.castling, self.en_passant)
+    }
+
+    pub fn piece_at(&self, at: logic::Square) -> Option<(logic::Piece, logic::Player)> {
+
+        let (rank, file) = at.to_indices();
+        let square = &self.backend.get_board()[rank][file];
+        if square.occupied {
+
+            // Convert piece
+            let piece = &square.piece;
+            let piece_type = match piece.piece_type {
+                backend::PieceType::Pawn       => logic::Piece::Pawn,
+                backend::PieceType::Rook       => logic::Piece::Rook,
+                backend::PieceType::Knight     => logic::Piece::Knight,
+                backend::PieceType::Bishop     => logic::Piece::Bishop,
+                backend::PieceType::Queen      => logic::Piece::Queen,
+                backend::PieceType::King       => logic::Piece::King,
+                backend::PieceType::Unoccupied => panic!(),
+            };
+
+            let player = if piece.white {
+                logic::Player::White
+            } else {
+                logic::Player::Black
+            };
+
+            Some((piece_type, player))
+        } else { None }
+    }
+
+    /// Whether moving from `from` to `to` requires choosing a promotion
+    pub fn promotes(&self, from: logic::Square, to: logic::Square) -> bool {
+
+        match self.piece_at(from) {
+            Some((logic::Piece::Pawn, owner)) => to.rank == owner.promotion_rank(),
+            _ => false,
+        }
+    }
+
+    /// The promotion a move from the protocol asks for, `Err` if it
+    /// doesn't fit the move
+    pub fn requested_promotion(
+        &self,
+        from: logic::Square,
+        to: logic::Square,
+        piece: protocol::Piece,
+    ) -> Result<Option<logic::Piece>, ()> {
+
+        if !self.promotes(from, to) {
+            return match piece {
+                protocol::Piece::None => Ok(None),
+                _ => Err(()),
+            };
+        }
+
+        match proto_to_logic(piece) {
+            // Clients without promotion support get a queen
+            None => Ok(Some(logic::Piece::Queen)),
+            Some((piece, player))
+                if player == self.to_move
+                && logic::PROMOTIONS.contains(&piece)
+                => Ok(Some(piece)),
+            Some(_) => Err(()),
+        }
+    }
+
+    /// Whether the backend would accept the move, without playing it
+    pub fn is_legal(&self, from: logic::Square, to: logic::Square) -> bool {
+
+        if !self.may_castle(from, to) {
+            return false;
+        }
+
+        if let Some(captured) = self.en_passant_capture(from, to) {
+            return self.capture_en_passant(from, to, captured).is_some();
+        }
+
+        let mut backend = self.backend.clone();
+        backend.input_move(backend_square(from), backend_square(to));
+        backend.check_move_valid()
+    }
+
+    /// The square of the pawn taken if moving from `from` to `to` captures
+    /// en passant
+    fn en_passant_capture(&self, from: logic::Square, to: logic::Square) -> Option<logic::Square> {
+
+        let forward = match self.to_move {
+            logic::Player::White => from.rank + 1 == to.rank,
+            logic::Player::Black => to.rank + 1 == from.rank,
+        };
+        let captured = logic::Square { rank: from.rank, file: to.file, };
+
+        let takes = self.en_passant == Some(to)
+            && forward
+            && from.file.abs_diff(to.file) == 1
+            && self.piece_at(from) == Some((logic::Piece::Pawn, self.to_move))
+            && self.piece_at(captured) == Some((logic::Piece::Pawn, self.to_move.other()));
+
+        takes.then_some(captured)
+    }
+
+    /// The backend after an en passant capture, `None` if it leaves the
+    /// king in check. Done by hand, as the backend doesn't know the en
+    /// passant square of positions from FEN.
+    fn capture_en_passant(
+        &self,
+        from: logic::Square,
+        to: logic::Square,
+        captured: logic::Square,
+    ) -> Option<backend::Game> {
+
+        let mut next = self.clone();
+        put(&mut next.backend, to, Some((logic::Piece::Pawn, self.to_move)));
+        put(&mut next.backend, from, None);
+        put(&mut next.backend, captured, None);
+        next.backend.white_turn = !next.backend.white_turn;
+
+        if san::in_check(&next.board(), self.to_move) {
+            None
+        } else {
+            Some(next.backend)
+        }
+    }
+
+    /// Whether a king moving from `from` to `to` either isn't castling or
+    /// still has the right to. Positions from FEN can take away rights the
+    /// backend doesn't know about.
+    fn may_castle(&self, from: logic::Square, to: logic::Square) -> bool {
+
+        match self.piece_at(from) {
+            Some((logic::Piece::King, player)) if from.file.abs_diff(to.file) == 2 => {
+                let (king_side, queen_side) = self.castling.rights(player);
+                if to.file > from.file { king_side } else { queen_side }
+            },
+            _ => true,
+        }
+    }
+
+    /// Squares the piece at `from` can legally move to
+    pub fn destinations(&self, from: logic::Square) -> Vec<logic::Square> {
+
+        let (piece, player) = match self.piece_at(from) {
+            Some(piece) => piece,
+            None => return Vec::new(),
+        };
+
+        logic::Square::all()
+            .filter(|&to| reachable(piece, player, from, to))
+            // Can't capture our own pieces
+            .filter(|&to| !matches!(self.piece_at(to), Some((_, owner)) if owner == player))
+            .filter(|&to| self.is_legal(from, to))
+            .collect()
+    }
+
+    /// All legal moves for `player`, with one move per promotion choice
+    pub fn legal_moves(&self, player: logic::Player) -> Vec<logic::Move> {
+
+        let mut moves = Vec::new();
+
+        for from in logic::Square::all() {
+
+            if !matches!(self.piece_at(from), Some((_, owner)) if owner == player) {
+                continue;
+            }
+
+            for to in self.destinations(from) {
+
+                if self.promotes(from, to) {
+                    for promotion in logic::PROMOTIONS {
+                        moves.push(logic::Move { from, to, promotion: Some(promotion), });
+                    }
+                } else {
+                    moves.push(logic::Move { from, to, promotion: None, });
+                }
+            }
+        }
+
+        moves
+    }
+
+    /// Plays a move on the backend, returns whether it was legal. Moves
+    /// that promote must name the piece, and no others may.
+    pub fn try_move(
+        &mut self,
+        from: logic::Square,
+        to: logic::Square,
+        promotion: Option<logic::Piece>,
+    ) -> bool {
+
+        let promotes = self.promotes(from, to);
+        match promotion {
+            Some(piece) if !promotes || !logic::PROMOTIONS.contains(&piece) => return false,
+            None if promotes => return false,
+            _ => (),
+        }
+
+        if !self.may_castle(from, to) {
+            return false;
+        }
+
+        let piece = self.piece_at(from).map(|(piece, _)| piece);
+        let capture = self.piece_at(to).is_some();
+        let en_passant = self.en_passant_capture(from, to);
+
+        if let Some(captured) = en_passant {
+            match self.capture_en_passant(from, to, captured) {
+                Some(backend) => self.backend = backend,
+                None => return false,
+            }
+        } else {
+            self.backend.input_move(backend_square(from), backend_square(to));
+
+            if !self.backend.check_move_valid() {
+                return false;
+            }
+
+            self.backend = self.backend.clone().do_turn();
+        }
+
+        if let Some(piece) = promotion {
+            let (rank, file) = to.to_indices();
+            let square = &mut self.backend.board[rank][file];
+            square.piece.piece_type = logic_to_backend(piece);
+        }
+
+        self.castling.touch(from);
+        self.castling.touch(to);
+        self.en_passant = match piece {
+            Some(logic::Piece::Pawn) if from.rank.abs_diff(to.rank) == 2 => Some(logic::Square {
+                rank: (from.rank + to.rank) / 2,
+                file: from.file,
+            }),
+            _ => None,
+        };
+        self.halfmove = if piece == Some(logic::Piece::Pawn) || capture { 0 } else { self.halfmove + 1 };
+        if self.to_move == logic::Player::Black {
+            self.fullmove += 1;
+        }
+        self.to_move = self.to_move.other();
+
+        // The backend decided on mate before the promoted piece was put in,
+        // and knows nothing of captures en passant played here
+        self.mate = if promotion.is_some() || en_passant.is_some() {
+            san::in_check(&self.board(), self.to_move) && self.legal_moves(self.to_move).is_empty()
+        } else {
+            self.backend.mate
+        };
+
+        let key = self.position_key();
+        self.positions.push(key);
+
+        true
+    }
+
+    /// Plays a move like `try_move`, returns it in SAN if it was legal
+    pub fn play(&mut self, mov: logic::Move) -> Option<String> {
+
+        let before = self.board();
+        if !self.try_move(mov.from, mov.to, mov.promotion) {
+            return None;
+        }
+
+        Some(san::san(&before, mov, &self.board(), self.mate()))
+    }
+
+    /// The legal move of `player` described by `san`, `None` if there
+    /// isn't exactly one
+    pub fn find_move(&self, player: logic::Player, san: &str) -> Option<logic::Move> {
+
+        let parsed = san::parse(san, player)?;
+
+        let mut candidates = logic::Square::all()
+            .filter(|&from| self.piece_at(from) == Some((parsed.piece, player)))
+            .filter(|from| parsed.from_file.is_none() || parsed.from_file == Some(from.file))
+            .filter(|from| parsed.from_rank.is_none() || parsed.from_rank == Some(from.rank))
+            .filter(|&from| self.is_legal(from, parsed.to));
+
+        let from = candidates.next()?;
+        if candidates.next().is_some() {
+            return None;
+        }
+
+        // Be lenient with exports that leave out the promotion
+        let promotion = match parsed.promotion {
+            None if self.promotes(from, parsed.to) => Some(logic::Piece::Queen),
+            promotion => promotion,
+        };
+
+        Some(logic::Move { from, to: parsed.to, promotion, })
+    }
+
+    pub fn board(&self) -> logic::Board {
+
+        let mut board = [[None; 8]; 8];
+        for at in logic::Square::all() {
+            let (rank, file) = at.to_indices();
+            board[rank][file] = self.piece_at(at);
+        }
+
+        board
+    }
+
+    /// The legal moves of `player` as the protocol wants them
+    pub fn to_protocol_moves(&self, player: logic::Player) -> Vec<protocol::Move> {
+
+        self.legal_moves(player)
+            .into_iter()
+            .map(|mov| {
+                let (start_x, start_y) = mov.from.to_protocol();
+                let (end_x, end_y) = mov.to.to_protocol();
+                protocol::Move {
+                    start_x,
+                    start_y,
+                    end_x,
+                    end_y,
+                    promotion: match mov.promotion {
+                        Some(piece) => logic_to_proto(piece, player),
+                        None => protocol::Piece::None,
+                    },
+                }
+            })
+            .collect()
+    }
+
+    pub fn to_protocol(&self) -> [[protocol::Piece; 8]; 8] {
+
+        let mut target = [[protocol::Piece::None; 8]; 8];
+
+        for (backend_row, target_row)
+            in std::iter::zip(self.backend.get_board(), &mut target)
+        {
+            for (backend_square, target_square)
+                in std::iter::zip(backend_row, target_row)
+            {
+                *target_square = if backend_square.occupied {
+                    match (
+                        backend_square.piece.piece_type,
+                        backend_square.piece.white,
+                        ) {
+
+                        (backend::PieceType::Pawn,   true) => protocol::Piece::WhitePawn,
+                        (backend::PieceType::Rook,   true) => protocol::Piece::WhiteRook,
+                        (backend::PieceType::Knight, true) => protocol::Piece::WhiteKnight,
+                        (backend::PieceType::Bishop, true) => protocol::Piece::WhiteBishop,
+                        (backend::PieceType::Queen,  true) => protocol::Piece::WhiteQueen,
+                        (backend::PieceType::King,   true) => protocol::Piece::WhiteKing,
+
+                        (backend::PieceType::Pawn,   false) => protocol::Piece::BlackPawn,
+                        (backend::PieceType::Rook,   false) => protocol::Piece::BlackRook,
+                        (backend::PieceType::Knight, false) => protocol::Piece::BlackKnight,
+                        (backend::PieceType::Bishop, false) => protocol::Piece::BlackBishop,
+                        (backend::PieceType::Queen,  false) => protocol::Piece::BlackQueen,
+                        (backend::PieceType::King,   false) => protocol::Piece::BlackKing,
+
+                        _ => panic!(),
+                    }
+                } else { protocol::Piece::None };
+            }
+        }
+
+        target
+    }
+}
+
+/// The backend takes squares as upper case algebraic, like "E4"
+fn backend_square(at: logic::Square) -> String {
+
+    at.to_algebraic().to_uppercase()
+}
+
+/// Whether `piece` could move from `from` to `to` on an empty board,
+/// spares the backend from checking moves that are hopeless anyway
+fn reachable(
+    piece: logic::Piece,
+    player: logic::Player,
+    from: logic::Square,
+    to: logic::Square,
+) -> bool {
+
+    let rows = to.rank as i8 - from.rank as i8;
+    let cols = to.file as i8 - from.file as i8;
+    let forward = match player {
+        logic::Player::White => 1,
+        logic::Player::Black => -1,
+    };
+
+    if (rows, cols) == (0, 0) {
+        return false;
+    }
+
+    let straight = rows == 0 || cols == 0;
+    let diagonal = rows.abs() == cols.abs();
+
+    match piece {
+        logic::Piece::Pawn   => (rows == forward && cols.abs() <= 1)
+                             || (rows == 2 * forward && cols == 0),
+        logic::Piece::Knight => matches!((rows.abs(), cols.abs()), (1, 2) | (2, 1)),
+        logic::Piece::Bishop => diagonal,
+        logic::Piece::Rook   => straight,
+        logic::Piece::Queen  => straight || diagonal,
+        // Two columns for castling
+        logic::Piece::King   => rows.abs() <= 1 && cols.abs() <= 2,
+    }
+}
+
+/// Sets what stands on `at` on the backend's board
+fn put(backend: &mut backend::Game, at: logic::Square, piece: Option<(logic::Piece, logic::Player)>) {
+
+    let (rank, file) = at.to_indices();
+    let square = &mut backend.board[rank][file];
+    match piece {
+        Some((piece, player)) => {
+            square.occupied = true;
+            square.piece.piece_type = logic_to_backend(piece);
+            square.piece.white = player == logic::Player::White;
+        },
+        None => {
+            square.occupied = false;
+            square.piece.piece_type = backend::PieceType::Unoccupied;
+        },
+    }
+}
+
+fn logic_to_backend(piece: logic::Piece) -> backend::PieceType {
+
+    match piece {
+        logic::Piece::Pawn   => backend::PieceType::Pawn,
+        logic::Piece::Rook   => backend::PieceType::Rook,
+        logic::Piece::Knight => backend::PieceType::Knight,
+        logic::Piece::Bishop => backend::PieceType::Bishop,
+        logic::Piece::Queen  => backend::PieceType::Queen,
+        logic::Piece::King   => backend::PieceType::King,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn game(fen: &str) -> Game {
+
+        Game::from_position(&fen::parse(fen).unwrap())
+    }
+
+    fn square(s: &str) -> logic::Square {
+
+        logic::Square::from_algebraic(s).unwrap()
+    }
+
+    fn mov(from: &str, to: &str, promotion: Option<logic::Piece>) -> logic::Move {
+
+        logic::Move { from: square(from), to: square(to), promotion, }
+    }
+
+    #[test]
+    fn mate_after_promotion() {
+
+        // A queen or rook on a8 mates, a knight doesn't even check
+        let start = game("7k/P7/6K1/7p/8/8/8/8 w - - 0 1");
+
+        let mut queen = start.clone();
+        assert_eq!(queen.play(mov("a7", "a8", Some(logic::Piece::Queen))).as_deref(), Some("a8=Q#"));
+        assert!(queen.mate());
+        assert!(matches!(queen.outcome(), Some(logic::State::CheckMate(logic::Player::White))));
+
+        let mut knight = start.clone();
+        assert_eq!(knight.play(mov("a7", "a8", Some(logic::Piece::Knight))).as_deref(), Some("a8=N"));
+        assert!(!knight.mate());
+        assert!(knight.outcome().is_none());
+    }
+
+    #[test]
+    fn promotions_only_on_moves_that_promote() {
+
+        let mut start = Game::new();
+        assert!(start.play(mov("e2", "e4", Some(logic::Piece::Queen))).is_none());
+        assert_eq!(start.piece_at(square("e2")), Some((logic::Piece::Pawn, logic::Player::White)));
+
+        let mut promoting = game("7k/P7/6K1/7p/8/8/8/8 w - - 0 1");
+        assert!(promoting.play(mov("a7", "a8", None)).is_none());
+        assert!(promoting.play(mov("a7", "a8", Some(logic::Piece::King))).is_none());
+        assert_eq!(promoting.to_move(), logic::Player::White);
+    }
+
+    #[test]
+    fn en_passant_from_fen() {
+
+        let mut game = game("rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 3");
+        let (e5, d6, d5) = (square("e5"), square("d6"), square("d5"));
+
+        assert!(game.is_legal(e5, d6));
+        assert!(game.destinations(e5).contains(&d6));
+        assert!(game.legal_moves(logic::Player::White).contains(&mov("e5", "d6", None)));
+
+        assert_eq!(game.play(mov("e5", "d6", None)).as_deref(), Some("exd6"));
+        assert_eq!(game.piece_at(d6), Some((logic::Piece::Pawn, logic::Player::White)));
+        assert_eq!(game.piece_at(d5), None);
+        assert_eq!(game.to_move(), logic::Player::Black);
+
+        // Play goes on as usual after it
+        assert_eq!(game.play(mov("c7", "d6", None)).as_deref(), Some("cxd6"));
+        assert_eq!(game.to_fen(), "rnbqkbnr/pp2pppp/3p4/8/8/8/PPPP1PPP/RNBQKBNR w KQkq - 0 4");
+    }
+
+    #[test]
+    fn en_passant_only_right_away() {
+
+        let mut game = game("rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 3");
+        game.play(mov("g1", "f3", None)).unwrap();
+        game.play(mov("g8", "f6", None)).unwrap();
+
+        assert!(game.play(mov("e5", "d6", None)).is_none());
+    }
+
+    #[test]
+    fn en_passant_into_check() {
+
+        // Taking would clear the fifth rank between the rook and the king
+        let game = game("8/8/8/K2pP2r/8/8/8/7k w - d6 0 1");
+        assert!(!game.is_legal(square("e5"), square("d6")));
+    }
+}
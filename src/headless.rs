@@ -0,0 +1,390 @@
+use chess_network_protocol::{
+    self as protocol,
+    ClientToServer as Cts,
+    ServerToClient as Stc,
+};
+
+use crate::logic;
+use crate::fen;
+use crate::pgn;
+use crate::lobby;
+use crate::takeback;
+use crate::clock::TimeControl;
+use crate::extensions::{ ClientHandshake, Extensions, };
+use crate::game::Game;
+use crate::door::{ Arrival, Door, };
+use crate::server::{ self, Server, };
+use crate::referee::{ Announcement, Offer, Referee, Takeback, outcome_joever, winner_joever, };
+use crate::tcp_handler::{ self as tcp, TcpHandler, };
+
+use std::net::{ SocketAddr, TcpStream, };
+use std::path::{ Path, PathBuf, };
+use std::thread;
+use std::time::Duration;
+
+// How long a client may take to send its handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+// How long to sleep between looking for messages
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A connected player
+pub struct Seat {
+    addr:        SocketAddr,
+    tcp_handler: TcpHandler<Cts, Stc>,
+    // What its client named in the handshake
+    extensions:  Extensions,
+}
+
+impl Seat {
+
+    /// Takes over `stream` once the handshake is done
+    pub fn new(stream: TcpStream, addr: SocketAddr, extensions: Extensions) -> Self {
+
+        Self { addr, tcp_handler: TcpHandler::new(stream), extensions, }
+    }
+}
+
+/// Referees a game between two clients, without a board of its own
+pub struct Host {
+
+    // Set when the host is one of many, to tell their output apart
+    id: Option<u32>,
+    referee: Referee,
+    white: Seat,
+    black: Seat,
+    // Spectators connect here, if anywhere
+    door: Option<Door>,
+    // Ongoing until the game ends
+    joever: protocol::Joever,
+}
+
+/// Waits for a white and a black player, then relays their moves until
+/// the game ends and saves it to `pgn_dir`
+pub fn run(
+    addr: SocketAddr,
+    position: Option<fen::Position>,
+    control: Option<TimeControl>,
+    pgn_dir: PathBuf,
+) {
+
+    let game = match position {
+        Some(position) => Game::from_position(&position),
+        None => Game::new(),
+    };
+
+    let listener = server::listen(addr);
+    println!("Waiting for two players on {}...", addr);
+
+    let mut white = None;
+    let mut black = None;
+    while white.is_none() || black.is_none() {
+
+        let (stream, addr) = match listener.accept() {
+            Ok(connection) => connection,
+            Err(e) => {
+                println!("Could not accept: {}", e);
+                continue;
+            },
+        };
+        println!("Connected! ({})", addr);
+
+        let (player, extensions) = match read_handshake(&stream) {
+            Ok(handshake) => handshake,
+            Err(e) => {
+                println!("Handshake with {} failed: {}", addr, e);
+                continue;
+            },
+        };
+
+        let seat = match player {
+            logic::Player::White => &mut white,
+            logic::Player::Black => &mut black,
+        };
+        if seat.is_some() {
+            println!("{} wants to play {:?}, which is taken", addr, player);
+            continue;
+        }
+        println!("{} plays {:?}", addr, player);
+        *seat = Some((stream, addr, extensions));
+
+        // Whoever came first may have given up waiting
+        for seat in [&mut white, &mut black] {
+            if let Some((_, addr, _)) = seat.take_if(|(stream, _, _)| !lobby::connected(stream)) {
+                println!("{} left before the game started", addr);
+            }
+        }
+    }
+    let (white, black) = (white.unwrap(), black.unwrap());
+
+    // Nobody's game starts before both are in, like on a lobby
+    for (stream, addr, _) in [&white, &black] {
+        if let Err(e) = send_handshake(stream, &game, control) {
+            println!("Game could not start, handshake with {} failed: {}", addr, e);
+            return;
+        }
+    }
+    let white = Seat::new(white.0, white.1, white.2);
+    let black = Seat::new(black.0, black.1, black.2);
+
+    // Only spectators are let in from now on
+    let door = match Door::new(listener) {
+        Ok(door) => Some(door),
+        Err(e) => {
+            println!("Spectators won't be able to join: {}", e);
+            None
+        },
+    };
+
+    let mut host = Host::new(None, game, white, black, door, control);
+    println!("Game started");
+    host.play();
+    host.save(&pgn_dir);
+}
+
+/// Reads the handshake of a new client and returns the colour it plays,
+/// and which of our extensions it understands
+pub fn read_handshake(stream: &TcpStream) -> Result<(logic::Player, Extensions), tcp::Error> {
+
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT)).map_err(tcp::Error::Io)?;
+    let ctshand: ClientHandshake = tcp::read(stream)?;
+
+    // Clients name the colour they want the server to play
+    let player = match ctshand.server_color {
+        protocol::Color::White => logic::Player::Black,
+        protocol::Color::Black => logic::Player::White,
+    };
+
+    Ok((player, Extensions::from_features(&ctshand.features)))
+}
+
+/// Answers a handshake read with `read_handshake`, the game starts
+/// for this client once it arrives
+pub fn send_handshake(
+    stream: &TcpStream,
+    game: &Game,
+    control: Option<TimeControl>,
+) -> Result<(), tcp::Error> {
+
+    Server::send_handshake(stream, game, game.to_move(), control)?;
+    stream.set_read_timeout(None).map_err(tcp::Error::Io)
+}
+
+impl Host {
+
+    /// Both players must have had their handshake, the clock starts now
+    pub fn new(
+        id: Option<u32>,
+        game: Game,
+        white: Seat,
+        black: Seat,
+        door: Option<Door>,
+        control: Option<TimeControl>,
+    ) -> Self {
+
+        let mut referee = Referee::new(game, control);
+        referee.start_clock();
+
+        Self {
+            id,
+            referee,
+            white,
+            black,
+            door,
+            joever: protocol::Joever::Ongoing,
+        }
+    }
+
+    pub fn play(&mut self) {
+
+        while matches!(self.joever, protocol::Joever::Ongoing) {
+
+            self.check_clock();
+
+            self.answer_door();
+
+            for player in [logic::Player::White, logic::Player::Black] {
+
+                if !matches!(self.joever, protocol::Joever::Ongoing) {
+                    break;
+                }
+
+                match self.seat(player).tcp_handler.read() {
+                    Ok(None) => (),
+                    Ok(Some(cts)) => self.handle(player, cts),
+                    Err(e) => {
+                        // Leaving loses the game
+                        self.log(format!("{:?} left: {}", player, e));
+                        self.resign(player);
+                    },
+                }
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Lets in spectators, the players are already here
+    fn answer_door(&mut self) {
+
+        match self.door.as_ref().and_then(Door::poll) {
+            Some(Arrival::Spectator { stream, addr, }) => self.referee.welcome_spectator(stream, addr),
+            Some(Arrival::Player { addr, .. }) => self.log(format!("{} wants to play, but the game has started", addr)),
+            None => (),
+        }
+    }
+
+    fn log(&self, message: String) {
+
+        match self.id {
+            Some(id) => println!("Game {}: {}", id, message),
+            None => println!("{}", message),
+        }
+    }
+
+    fn seat(&self, player: logic::Player) -> &Seat {
+
+        match player {
+            logic::Player::White => &self.white,
+            logic::Player::Black => &self.black,
+        }
+    }
+
+    /// Errors show up when reading from the same player later
+    fn send(&self, to: logic::Player, stc: Stc) {
+
+        let _ = self.seat(to).tcp_handler.write(stc);
+    }
+
+    /// Spectators have been told already
+    fn send_both(&self, announcement: &Announcement) {
+
+        self.send(logic::Player::White, announcement.message());
+        self.send(logic::Player::Black, announcement.message());
+    }
+
+    /// Tells `to` something the protocol has no message for
+    fn send_notice(&self, to: logic::Player, message: &str) {
+
+        self.send(to, self.referee.notice(message));
+    }
+
+    fn handle(&mut self, from: logic::Player, cts: Cts) {
+
+        match cts {
+            Cts::Move(mov) if takeback::is_request(&mov) && self.seat(from).extensions.takeback =>
+                self.request_takeback(from),
+            Cts::Move(mov) => self.make_move(from, mov),
+            Cts::Resign => {
+                self.log(format!("{:?} resigned", from));
+                self.resign(from);
+            },
+            Cts::Draw => self.offer_draw(from),
+        }
+    }
+
+    fn make_move(&mut self, player: logic::Player, mov: protocol::Move) {
+
+        let number = self.referee.game().to_position().fullmove;
+        let played = match self.referee.make_move(player, mov) {
+            Ok(played) => played,
+            Err(reason) => {
+                self.log(format!("{:?} tried a move that can't be played: {}", player, reason));
+                self.send_notice(player, reason);
+                return;
+            },
+        };
+
+        match player {
+            logic::Player::White => self.log(format!("{}. {}", number, played.san)),
+            logic::Player::Black => self.log(format!("{}. ... {}", number, played.san)),
+        }
+        self.send_both(&played.announcement);
+
+        let joever = outcome_joever(played.outcome);
+        if !matches!(joever, protocol::Joever::Ongoing) {
+            self.finish(joever);
+        }
+    }
+
+    fn resign(&mut self, loser: logic::Player) {
+
+        let announcement = self.referee.resign(loser);
+        self.send(loser.other(), announcement.message());
+        self.finish(winner_joever(loser.other()));
+    }
+
+    /// `from` either offers a draw or accepts the other player's offer
+    fn offer_draw(&mut self, from: logic::Player) {
+
+        let offer = self.referee.offer_draw(from);
+        if matches!(offer, Offer::Repeated) {
+            return;
+        }
+
+        // The same message tells the other player about the offer, or
+        // that it was accepted
+        self.send(from.other(), self.referee.draw_message());
+
+        match offer {
+            Offer::Accepted => {
+                self.log(format!("{:?} accepted the draw", from));
+                self.finish(protocol::Joever::Draw);
+            },
+            _ => self.log(format!("{:?} offers a draw", from)),
+        }
+    }
+
+    /// `from` either asks for a takeback or agrees to the other player's
+    /// request
+    fn request_takeback(&mut self, from: logic::Player) {
+
+        // The other client couldn't answer
+        if !self.seat(from.other()).extensions.takeback {
+            self.send_notice(from, takeback::DECLINED);
+            return;
+        }
+
+        match self.referee.request_takeback(from) {
+            Takeback::Repeated => (),
+            Takeback::NothingToTakeBack => self.send_notice(from, takeback::DECLINED),
+            Takeback::Requested => {
+                self.log(format!("{:?} asks to take back a move", from));
+                self.send_notice(from.other(), takeback::REQUESTED);
+            },
+            Takeback::TakenBack(announcement) => {
+                self.log(format!("{:?} agreed to take back", from));
+                self.send_both(&announcement);
+            },
+        }
+    }
+
+    /// Ends the game if someone's time ran out
+    fn check_clock(&mut self) {
+
+        let (loser, announcement) = match self.referee.check_clock() {
+            Some(flagged) => flagged,
+            None => return,
+        };
+        self.log(format!("{:?} ran out of time", loser));
+
+        self.send_both(&announcement);
+        self.finish(winner_joever(loser.other()));
+    }
+
+    fn finish(&mut self, joever: protocol::Joever) {
+
+        self.log(format!("Game over: {}", pgn::result(joever)));
+        self.joever = joever;
+    }
+
+    pub fn save(&self, dir: &Path) {
+
+        let white = self.white.addr.to_string();
+        let black = self.black.addr.to_string();
+        let start = self.referee.game().start_position();
+        match pgn::save(dir, Some(&start), self.referee.history(), &white, &black, self.joever) {
+            Ok(path) => self.log(format!("Saved game to {}", path.display())),
+            Err(e) => self.log(format!("Could not save game: {}", e)),
+        }
+    }
+}
@@ -0,0 +1,472 @@
+use crate::logic;
+use crate::fen;
+use crate::clock::TimeControl;
+use crate::game::Game;
+use crate::server;
+use crate::extensions::Extensions;
+use crate::headless::{ self, Host, Seat, };
+use crate::door::{ Arrival, Door, };
+
+use std::io::{ self, Read, Write, };
+use std::net::{ SocketAddr, TcpStream, };
+use std::path::PathBuf;
+use std::sync::{ Arc, Mutex, };
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+
+// How long a client may take to say what it wants
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+// Longest line a client may send before its handshake
+const MAX_LINE: usize = 64;
+
+// Lines a client sends instead of going straight to the handshake
+const LIST: &str = "LIST";
+const NEW: &str = "NEW";
+const JOIN: &str = "JOIN";
+/// Sent by spectators, which get the server's handshake without one of
+/// their own. On a lobby it's followed by the ID of the game to watch.
+pub const SPECTATE: &str = "SPECTATE";
+
+// Listed in place of the colour for games already under way
+const PLAYING: &str = "playing";
+
+// The lobby starts its answers to NEW and JOIN with one of these
+const OK: &str = "OK";
+const ERR: &str = "ERR";
+
+/// How a client finds its game on a lobby
+#[derive(Clone, Copy)]
+pub enum Entry {
+    /// Send the handshake right away, the lobby pairs the client with
+    /// anyone waiting for its colour. Works with any server.
+    Any,
+    /// Open a new game and wait for someone to join it
+    New,
+    /// Join the open game with this ID, playing the colour that's left
+    Join(u32),
+}
+
+/// Asks the lobby for a game, before the handshake. Returns the colour
+/// the lobby gives the client, if it picks one. Being turned away is an
+/// error of kind `Other`.
+pub fn enter(stream: &TcpStream, entry: Entry) -> io::Result<Option<logic::Player>> {
+
+    match entry {
+        Entry::Any => Ok(None),
+        Entry::New => {
+            write_line(stream, NEW)?;
+            let answer = read_line(stream)?;
+            match answer.split_once(' ') {
+                Some((OK, id)) => match id.parse::<u32>() {
+                    Ok(id) => {
+                        println!("Opened game {}, waiting for an opponent...", id);
+                        Ok(None)
+                    },
+                    Err(_) => Err(io::Error::other(format!("invalid game ID {}", id))),
+                },
+                Some((ERR, reason)) => Err(io::Error::other(reason.to_string())),
+                _ => Err(io::Error::other(format!("unexpected answer {}", answer))),
+            }
+        },
+        Entry::Join(id) => {
+            write_line(stream, &format!("{} {}", JOIN, id))?;
+            let answer = read_line(stream)?;
+            match answer.split_once(' ') {
+                Some((OK, colour)) => match parse_colour(colour) {
+                    Some(player) => Ok(Some(player)),
+                    None => Err(io::Error::other(format!("unknown colour {}", colour))),
+                },
+                Some((ERR, reason)) => Err(io::Error::other(reason.to_string())),
+                _ => Err(io::Error::other(format!("unexpected answer {}", answer))),
+            }
+        },
+    }
+}
+
+/// Asks to watch the game, the one with ID `game` on a lobby, before the
+/// server's handshake. Being turned away is an error of kind `Other`.
+pub fn spectate(stream: &TcpStream, game: Option<u32>) -> io::Result<()> {
+
+    match game {
+        Some(id) => write_line(stream, &format!("{} {}", SPECTATE, id))?,
+        None => write_line(stream, SPECTATE)?,
+    }
+
+    // The handshake is JSON, anything else is a refusal
+    let mut first = [0];
+    match stream.peek(&mut first)? {
+        0 => Err(io::ErrorKind::UnexpectedEof.into()),
+        _ if first[0] == b'{' => Ok(()),
+        _ => {
+            let answer = read_line(stream)?;
+            match answer.split_once(' ') {
+                Some((ERR, reason)) => Err(io::Error::other(reason.to_string())),
+                _ => Err(io::Error::other(format!("unexpected answer {}", answer))),
+            }
+        },
+    }
+}
+
+/// Prints the games on the lobby at the first of `addrs` that answers
+pub fn list(addrs: &[SocketAddr]) -> io::Result<()> {
+
+    let stream = TcpStream::connect(addrs)?;
+    write_line(&stream, LIST)?;
+
+    let mut games = 0;
+    // The lobby closes the connection after the last game
+    while let Ok(line) = read_line(&stream) {
+        match line.split_once(' ') {
+            Some((id, PLAYING)) => println!("Game {}: under way, watch with --game {}", id, id),
+            Some((id, colour)) => println!("Game {}: join as {}", id, colour),
+            None => println!("{}", line),
+        }
+        games += 1;
+    }
+
+    if games == 0 {
+        println!("No games");
+    }
+    Ok(())
+}
+
+/// What every game on the lobby starts with
+#[derive(Clone)]
+struct Settings {
+    position: Option<fen::Position>,
+    control:  Option<TimeControl>,
+    pgn_dir:  PathBuf,
+}
+
+/// A game waiting for its second player
+struct Open {
+    id:         u32,
+    // Whoever opened the game plays this colour
+    player:     logic::Player,
+    addr:       SocketAddr,
+    stream:     TcpStream,
+    extensions: Extensions,
+}
+
+#[derive(Default)]
+struct Lobby {
+    next_id: u32,
+    open:    Vec<Open>,
+    // Games under way by ID, with the way in for their spectators
+    playing: Vec<(u32, Sender<Arrival>)>,
+}
+
+impl Lobby {
+
+    fn new_id(&mut self) -> u32 {
+
+        self.next_id += 1;
+        self.next_id
+    }
+
+    /// Forgets games whose player left while waiting
+    fn prune(&mut self) {
+
+        self.open.retain(|open| connected(&open.stream));
+    }
+
+    /// Removes the first open game `pick` likes, its player still there
+    fn take(&mut self, pick: impl Fn(&Open) -> bool) -> Option<Open> {
+
+        self.prune();
+        let i = self.open.iter().position(pick)?;
+        Some(self.open.remove(i))
+    }
+}
+
+/// Hosts any number of games at once. Clients list, open or join them
+/// with a line of text before their handshake, or are paired with the
+/// first game waiting for their colour if they just send the handshake.
+pub fn run(
+    addr: SocketAddr,
+    position: Option<fen::Position>,
+    control: Option<TimeControl>,
+    pgn_dir: PathBuf,
+) {
+
+    let listener = server::listen(addr);
+    println!("Lobby open on {}", addr);
+
+    let settings = Settings { position, control, pgn_dir, };
+    let lobby = Arc::new(Mutex::new(Lobby::default()));
+
+    loop {
+
+        let (stream, addr) = match listener.accept() {
+            Ok(connection) => connection,
+            Err(e) => {
+                println!("Could not accept: {}", e);
+                continue;
+            },
+        };
+
+        // Each client gets a thread, which goes on to host its game if
+        // it completes one
+        let lobby = lobby.clone();
+        let settings = settings.clone();
+        thread::spawn(move || welcome(stream, addr, &lobby, &settings));
+    }
+}
+
+/// Finds out what a new client wants and does it
+fn welcome(stream: TcpStream, addr: SocketAddr, lobby: &Mutex<Lobby>, settings: &Settings) {
+
+    if let Err(e) = stream.set_read_timeout(Some(REQUEST_TIMEOUT)) {
+        println!("Could not talk to {}: {}", addr, e);
+        return;
+    }
+
+    let request = match read_request(&stream) {
+        Ok(request) => request,
+        Err(e) => {
+            println!("Could not read from {}: {}", addr, e);
+            return;
+        },
+    };
+
+    match request.as_deref().map(|line| line.split_once(' ').unwrap_or((line, ""))) {
+        None => pair(stream, addr, lobby, settings),
+        Some((LIST, "")) => list_games(&stream, lobby),
+        Some((NEW, "")) => {
+            let id = lobby.lock().unwrap().new_id();
+            if write_line(&stream, &format!("{} {}", OK, id)).is_ok() {
+                open(stream, addr, id, lobby);
+            }
+        },
+        Some((JOIN, id)) => match id.parse() {
+            Ok(id) => join(stream, addr, id, lobby, settings),
+            Err(_) => refuse(&stream, "Invalid game ID"),
+        },
+        Some((SPECTATE, "")) => refuse(&stream, "Name the game to watch"),
+        Some((SPECTATE, id)) => match id.parse() {
+            Ok(id) => watch(stream, addr, id, lobby),
+            Err(_) => refuse(&stream, "Invalid game ID"),
+        },
+        Some(_) => {
+            println!("{} sent an unknown request", addr);
+            refuse(&stream, "Unknown request");
+        },
+    }
+}
+
+/// The line a new client sends before its handshake, `None` if it went
+/// straight to the handshake
+pub fn read_request(stream: &TcpStream) -> io::Result<Option<String>> {
+
+    // Plain clients start with the handshake, which is JSON
+    let mut first = [0];
+    match stream.peek(&mut first)? {
+        0 => Err(io::ErrorKind::UnexpectedEof.into()),
+        _ if first[0] == b'{' => Ok(None),
+        _ => read_line(stream).map(Some),
+    }
+}
+
+/// Answers a request with why it can't be done
+pub fn refuse(stream: &TcpStream, reason: &str) {
+
+    let _ = write_line(stream, &format!("{} {}", ERR, reason));
+}
+
+fn list_games(stream: &TcpStream, lobby: &Mutex<Lobby>) {
+
+    let lines: Vec<_> = {
+        let mut lobby = lobby.lock().unwrap();
+        lobby.prune();
+        let open = lobby.open.iter()
+            .map(|open| format!("{} {}", open.id, colour_name(open.player.other())));
+        let playing = lobby.playing.iter()
+            .map(|(id, _)| format!("{} {}", id, PLAYING));
+        open.chain(playing).collect()
+    };
+
+    for line in lines {
+        if write_line(stream, &line).is_err() {
+            return;
+        }
+    }
+}
+
+/// Hands a spectator to the game with ID `id`
+fn watch(stream: TcpStream, addr: SocketAddr, id: u32, lobby: &Mutex<Lobby>) {
+
+    let lobby = lobby.lock().unwrap();
+    match lobby.playing.iter().find(|(playing, _)| *playing == id) {
+        // Nobody is listening if the game just ended
+        Some((_, spectators)) => {
+            let _ = spectators.send(Arrival::Spectator { stream, addr, });
+        },
+        None => refuse(&stream, &format!("No game under way with ID {}", id)),
+    }
+}
+
+/// Lists a new game with ID `id`, once its player has had its handshake
+fn open(stream: TcpStream, addr: SocketAddr, id: u32, lobby: &Mutex<Lobby>) {
+
+    let (player, extensions) = match headless::read_handshake(&stream) {
+        Ok(handshake) => handshake,
+        Err(e) => {
+            println!("Handshake with {} failed: {}", addr, e);
+            return;
+        },
+    };
+
+    println!("{} opened game {} as {:?}", addr, id, player);
+    lobby.lock().unwrap().open.push(Open { id, player, addr, stream, extensions, });
+}
+
+fn join(stream: TcpStream, addr: SocketAddr, id: u32, lobby: &Mutex<Lobby>, settings: &Settings) {
+
+    let open = match lobby.lock().unwrap().take(|open| open.id == id) {
+        Some(open) => open,
+        None => {
+            refuse(&stream, &format!("No open game with ID {}", id));
+            return;
+        },
+    };
+
+    let player = open.player.other();
+    let handshake = write_line(&stream, &format!("{} {}", OK, colour_name(player)))
+        .map_err(|e| e.to_string())
+        .and_then(|_| headless::read_handshake(&stream).map_err(|e| e.to_string()));
+
+    match handshake {
+        Ok((asked, extensions)) if asked == player => start(open, stream, addr, extensions, lobby, settings),
+        Ok((asked, _)) => {
+            println!("{} asked to play {:?} in game {}, which is taken", addr, asked, id);
+            lobby.lock().unwrap().open.push(open);
+        },
+        Err(e) => {
+            println!("Handshake with {} failed: {}", addr, e);
+            lobby.lock().unwrap().open.push(open);
+        },
+    }
+}
+
+/// Starts a game with the first one waiting for the client's colour, or
+/// opens one if nobody is
+fn pair(stream: TcpStream, addr: SocketAddr, lobby: &Mutex<Lobby>, settings: &Settings) {
+
+    let (player, extensions) = match headless::read_handshake(&stream) {
+        Ok(handshake) => handshake,
+        Err(e) => {
+            println!("Handshake with {} failed: {}", addr, e);
+            return;
+        },
+    };
+
+    let mut waiting = lobby.lock().unwrap();
+    match waiting.take(|open| open.player == player.other()) {
+        Some(open) => {
+            drop(waiting);
+            start(open, stream, addr, extensions, lobby, settings);
+        },
+        None => {
+            let id = waiting.new_id();
+            println!("{} opened game {} as {:?}", addr, id, player);
+            waiting.open.push(Open { id, player, addr, stream, extensions, });
+        },
+    }
+}
+
+/// Plays out an open game against the client that joined it, on the
+/// calling thread
+fn start(
+    open: Open,
+    stream: TcpStream,
+    addr: SocketAddr,
+    extensions: Extensions,
+    lobby: &Mutex<Lobby>,
+    settings: &Settings,
+) {
+
+    let game = match settings.position {
+        Some(position) => Game::from_position(&position),
+        None => Game::new(),
+    };
+
+    for (stream, addr) in [(&open.stream, open.addr), (&stream, addr)] {
+        if let Err(e) = headless::send_handshake(stream, &game, settings.control) {
+            println!("Game {} could not start, handshake with {} failed: {}", open.id, addr, e);
+            return;
+        }
+    }
+
+    let creator = Seat::new(open.stream, open.addr, open.extensions);
+    let joiner = Seat::new(stream, addr, extensions);
+    let (white, black) = match open.player {
+        logic::Player::White => (creator, joiner),
+        logic::Player::Black => (joiner, creator),
+    };
+    println!("Game {} started, {} joined", open.id, addr);
+
+    let (door, spectators) = Door::inner();
+    lobby.lock().unwrap().playing.push((open.id, spectators));
+
+    let mut host = Host::new(Some(open.id), game, white, black, Some(door), settings.control);
+    host.play();
+    lobby.lock().unwrap().playing.retain(|(id, _)| *id != open.id);
+    host.save(&settings.pgn_dir);
+}
+
+/// Whether the other end is still there, without reading anything
+pub fn connected(stream: &TcpStream) -> bool {
+
+    let mut byte = [0];
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+
+    let connected = match stream.peek(&mut byte) {
+        Ok(0) => false,
+        Ok(_) => true,
+        Err(e) => e.kind() == io::ErrorKind::WouldBlock,
+    };
+    stream.set_nonblocking(false).is_ok() && connected
+}
+
+fn colour_name(player: logic::Player) -> &'static str {
+
+    match player {
+        logic::Player::White => "white",
+        logic::Player::Black => "black",
+    }
+}
+
+fn parse_colour(colour: &str) -> Option<logic::Player> {
+
+    match colour {
+        "white" => Some(logic::Player::White),
+        "black" => Some(logic::Player::Black),
+        _ => None,
+    }
+}
+
+fn write_line(mut stream: &TcpStream, line: &str) -> io::Result<()> {
+
+    writeln!(stream, "{}", line)
+}
+
+/// Reads a line a byte at a time, so the handshake after it stays unread
+fn read_line(mut stream: &TcpStream) -> io::Result<String> {
+
+    let mut line = Vec::new();
+    let mut byte = [0];
+    while line.len() < MAX_LINE {
+        if stream.read(&mut byte)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if byte[0] == b'\n' {
+            return Ok(String::from_utf8_lossy(&line).trim().to_string());
+        }
+        line.push(byte[0]);
+    }
+
+    Err(io::Error::new(io::ErrorKind::InvalidData, "line too long"))
+}
@@ -0,0 +1,241 @@
+use crate::logic;
+use crate::game::Game;
+use crate::fen;
+
+use std::time::Duration;
+
+/// Both players share the same screen
+pub struct Local {
+
+    game: Game,
+    state: logic::State,
+    // The player whose turn it is
+    player: logic::Player,
+    history: Vec<String>,
+    // The game before each move, for taking them back
+    undo: Vec<Game>,
+    draw_offer: Option<logic::Player>,
+}
+
+impl Local {
+
+    pub fn new() -> logic::Layer {
+
+        Box::new(Self {
+            game: Game::new(),
+            state: logic::State::SelectPiece,
+            player: logic::Player::White,
+            history: Vec::new(),
+            undo: Vec::new(),
+            draw_offer: None,
+        })
+    }
+
+    fn make_move(
+        &mut self,
+        from: logic::Square,
+        dst: logic::Square,
+        promotion: Option<logic::Piece>,
+    ) {
+
+        let mov = logic::Move { from, to: dst, promotion, };
+        let before = self.game.clone();
+        match self.game.play(mov) {
+            Some(san) => {
+                self.history.push(san);
+                self.undo.push(before);
+            },
+            None => {
+                self.state = logic::State::SelectPiece;
+                return;
+            },
+        }
+
+        // Moving instead of answering turns the offer down
+        if self.draw_offer == Some(self.player.other()) {
+            self.draw_offer = None;
+        }
+
+        self.state = match self.game.outcome() {
+            Some(state) => state,
+            None => {
+                self.player = self.player.other();
+                logic::State::SelectPiece
+            },
+        };
+    }
+}
+
+impl logic::Interface for Local {
+
+    fn get_state(&self) -> logic::State {
+
+        self.state
+    }
+
+    fn get_player(&self) -> Option<logic::Player> {
+
+        None
+    }
+
+    fn update(&mut self) {}
+
+    fn get_piece_at(&self, at: logic::Square) -> Option<(logic::Piece, logic::Player)> {
+
+        self.game.piece_at(at)
+    }
+
+    fn get_destinations(&self, from: logic::Square) -> Vec<logic::Square> {
+
+        self.game.destinations(from)
+    }
+
+    fn select_piece(&mut self, at: logic::Square) {
+
+        if !matches!(self.state, logic::State::SelectPiece) {
+            return;
+        }
+
+        // Must be the piece of whoever's turn it is
+        match self.game.piece_at(at) {
+            Some((_, player)) if player == self.player => (),
+            _ => return,
+        }
+
+        self.state = logic::State::SelectMove { from: at, };
+    }
+
+    fn play_move(&mut self, dst: logic::Square) {
+
+        let from = match self.state {
+            logic::State::SelectMove { from } => from,
+            _ => return,
+        };
+
+        // Pawns reaching the last rank need a promotion first
+        if self.game.promotes(from, dst) {
+            self.state = logic::State::SelectPromotion { from, at: dst, };
+            return;
+        }
+
+        self.make_move(from, dst, None);
+    }
+
+    fn select_promotion(&mut self, piece: logic::Piece) {
+
+        let (from, at) = match self.state {
+            logic::State::SelectPromotion { from, at } => (from, at),
+            _ => return,
+        };
+
+        self.make_move(from, at, Some(piece));
+    }
+
+    fn cancel_promotion(&mut self) {
+
+        if let logic::State::SelectPromotion { from, .. } = self.state {
+            self.state = logic::State::SelectMove { from, };
+        }
+    }
+
+    fn get_history(&self) -> &[String] {
+
+        &self.history
+    }
+
+    fn get_fen(&self) -> String {
+
+        self.game.to_fen()
+    }
+
+    fn get_start_position(&self) -> Option<fen::Position> {
+
+        Some(self.game.start_position())
+    }
+
+    fn get_clock(&self, _player: logic::Player) -> Option<Duration> {
+
+        None
+    }
+
+    fn resign(&mut self) {
+
+        if self.state.is_playing() {
+            self.state = logic::State::Resigned { winner: self.player.other(), };
+        }
+    }
+
+    /// Both sides share the screen, so an offer from the other side
+    /// accepts the first
+    fn offer_draw(&mut self) {
+
+        if !self.state.is_playing() || self.draw_offer == Some(self.player) {
+            return;
+        }
+
+        match self.draw_offer {
+            Some(_) => self.state = logic::State::Drawn { reason: logic::DrawReason::Agreement, },
+            None => self.draw_offer = Some(self.player),
+        }
+    }
+
+    fn decline_draw(&mut self) {
+
+        if self.draw_offer == Some(self.player.other()) {
+            self.draw_offer = None;
+        }
+    }
+
+    fn get_draw_offer(&self) -> Option<logic::Player> {
+
+        self.draw_offer
+    }
+
+    /// Takes back the last move, even after the game ended
+    fn request_takeback(&mut self) {
+
+        if !self.state.is_playing() && !self.state.is_over() {
+            return;
+        }
+
+        let game = match self.undo.pop() {
+            Some(game) => game,
+            None => return,
+        };
+        self.history.pop();
+        self.player = game.to_move();
+        self.game = game;
+        self.draw_offer = None;
+        self.state = logic::State::SelectPiece;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn square(s: &str) -> logic::Square {
+
+        logic::Square::from_algebraic(s).unwrap()
+    }
+
+    #[test]
+    fn only_the_other_side_accepts_a_draw() {
+
+        let mut local = Local::new();
+        local.offer_draw();
+        local.offer_draw();
+        assert!(local.get_state().is_playing());
+        assert_eq!(local.get_draw_offer(), Some(logic::Player::White));
+
+        // The offer stands until the other side answers
+        local.decline_draw();
+        assert_eq!(local.get_draw_offer(), Some(logic::Player::White));
+
+        local.select_piece(square("e2"));
+        local.play_move(square("e4"));
+        local.offer_draw();
+        assert!(matches!(local.get_state(), logic::State::Drawn { reason: logic::DrawReason::Agreement, }));
+    }
+}
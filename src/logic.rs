@@ -1,12 +1,95 @@
+use crate::fen;
+use chess_network_protocol as protocol;
+
+use std::net::SocketAddr;
 
 #[derive(Clone, Copy, Eq, PartialEq)]
 pub enum State {
     OpponentTurn,
     ResponsePending,
     SelectPiece,
-    SelectMove { from: (u8, u8), },
-    SelectPromotion { at: (u8, u8), },
+    SelectMove { from: Square, },
+    SelectPromotion { from: Square, at: Square, },
     CheckMate(Player),
+    /// The loser's clock ran out
+    OutOfTime { winner: Player, },
+    /// The loser gave up
+    Resigned { winner: Player, },
+    Drawn { reason: DrawReason, },
+    Disconnected { reason: Disconnect, },
+    Reconnecting,
+    /// Listening for the opponent, who can connect to `addr`
+    WaitingForOpponent { addr: SocketAddr, },
+    /// Trying to reach the server at `addr`, `attempt` counts from 1
+    Connecting { addr: SocketAddr, attempt: u32, },
+    /// Looking through a finished game, nothing can be moved
+    Replay,
+    /// Following someone else's game
+    Watching { to_move: Player, },
+}
+
+impl State {
+
+    /// Whether the game has ended
+    pub fn is_over(&self) -> bool {
+
+        matches!(self,
+            State::CheckMate(_)
+            | State::OutOfTime { .. }
+            | State::Resigned { .. }
+            | State::Drawn { .. }
+        )
+    }
+
+    /// Whether the game is still going and the opponent is there
+    pub fn is_playing(&self) -> bool {
+
+        matches!(self,
+            State::OpponentTurn
+            | State::ResponsePending
+            | State::SelectPiece
+            | State::SelectMove { .. }
+            | State::SelectPromotion { .. }
+        )
+    }
+
+    pub fn winner(&self) -> Option<Player> {
+
+        match *self {
+            State::CheckMate(player) => Some(player),
+            State::OutOfTime { winner } => Some(winner),
+            State::Resigned { winner } => Some(winner),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum DrawReason {
+    /// The player to move has no legal moves but isn't in check
+    Stalemate,
+    /// Neither side has the pieces left to mate
+    InsufficientMaterial,
+    /// The same position came up three times
+    Repetition,
+    /// Fifty moves by each side without a capture or pawn move
+    FiftyMoves,
+    /// One side offered and the other accepted
+    Agreement,
+    /// The server ended the game as a draw without saying why
+    Declared,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Disconnect {
+    /// The opponent closed the connection
+    Closed,
+    /// The connection broke
+    Broken,
+    /// The opponent sent something we don't understand
+    Invalid,
+    /// The server turned us away before the game started
+    Refused,
 }
 
 #[derive(Clone, Copy, Eq, PartialEq, Debug)]
@@ -22,9 +105,17 @@ impl Player {
             Player::Black => Player::White,
         }
     }
+
+    /// The rank on which this player's pawns promote
+    pub fn promotion_rank(&self) -> u8 {
+        match *self {
+            Player::White => 7,
+            Player::Black => 0,
+        }
+    }
 }
 
-#[derive(Clone, Copy, Eq, PartialEq)]
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
 pub enum Piece {
     Pawn,
     Rook,
@@ -34,13 +125,279 @@ pub enum Piece {
     King,
 }
 
+/// A square on the board. This is the only place that knows how the
+/// different coordinate systems map onto each other.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct Square {
+    /// Counted from white's side, 0 is the first rank
+    pub rank: u8,
+    /// Counted from the A file
+    pub file: u8,
+}
+
+impl Square {
+
+    pub fn new(rank: u8, file: u8) -> Option<Self> {
+
+        if rank < 8 && file < 8 {
+            Some(Self { rank, file, })
+        } else {
+            None
+        }
+    }
+
+    /// Every square, rank by rank
+    pub fn all() -> impl Iterator<Item = Self> {
+
+        (0..8).flat_map(|rank| (0..8).map(move |file| Self { rank, file, }))
+    }
+
+    /// Parses squares like "e4", in either case
+    pub fn from_algebraic(s: &str) -> Option<Self> {
+
+        match s.as_bytes() {
+            [file, rank] => {
+                let file = file.to_ascii_lowercase().checked_sub(b'a')?;
+                let rank = rank.checked_sub(b'1')?;
+                Self::new(rank, file)
+            },
+            _ => None,
+        }
+    }
+
+    pub fn to_algebraic(self) -> String {
+
+        format!("{}{}", (b'a' + self.file) as char, self.rank + 1)
+    }
+
+    /// Protocol moves have x as the file and y as the rank, and boards
+    /// are indexed `board[y][x]`
+    pub fn from_protocol(x: usize, y: usize) -> Option<Self> {
+
+        if x < 8 && y < 8 {
+            Self::new(y as u8, x as u8)
+        } else {
+            None
+        }
+    }
+
+    /// As (x, y), see `from_protocol`
+    pub fn to_protocol(self) -> (usize, usize) {
+
+        (self.file as usize, self.rank as usize)
+    }
+
+    /// The backend's board is indexed `board[rank][file]`
+    pub fn to_indices(self) -> (usize, usize) {
+
+        (self.rank as usize, self.file as usize)
+    }
+}
+
+impl std::fmt::Display for Square {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+
+        write!(f, "{}", self.to_algebraic())
+    }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct Move {
+    pub from:      Square,
+    pub to:        Square,
+    pub promotion: Option<Piece>,
+}
+
+/// Pieces on the board, indexed by `Square::to_indices`
+pub type Board = [[Option<(Piece, Player)>; 8]; 8];
+
+/// The piece on a protocol board, if any
+pub fn proto_to_logic(piece: protocol::Piece) -> Option<(Piece, Player)> {
+
+    match piece {
+        protocol::Piece::None        => None,
+
+        protocol::Piece::BlackPawn   => Some((Piece::Pawn, Player::Black)),
+        protocol::Piece::BlackRook   => Some((Piece::Rook, Player::Black)),
+        protocol::Piece::BlackKnight => Some((Piece::Knight, Player::Black)),
+        protocol::Piece::BlackBishop => Some((Piece::Bishop, Player::Black)),
+        protocol::Piece::BlackQueen  => Some((Piece::Queen, Player::Black)),
+        protocol::Piece::BlackKing   => Some((Piece::King, Player::Black)),
+
+        protocol::Piece::WhitePawn   => Some((Piece::Pawn, Player::White)),
+        protocol::Piece::WhiteRook   => Some((Piece::Rook, Player::White)),
+        protocol::Piece::WhiteKnight => Some((Piece::Knight, Player::White)),
+        protocol::Piece::WhiteBishop => Some((Piece::Bishop, Player::White)),
+        protocol::Piece::WhiteQueen  => Some((Piece::Queen, Player::White)),
+        protocol::Piece::WhiteKing   => Some((Piece::King, Player::White)),
+    }
+}
+
+/// As a protocol piece, see `proto_to_logic`
+pub fn logic_to_proto(piece: Piece, player: Player) -> protocol::Piece {
+
+    match (piece, player) {
+        (Piece::Pawn,   Player::Black) => protocol::Piece::BlackPawn,
+        (Piece::Rook,   Player::Black) => protocol::Piece::BlackRook,
+        (Piece::Knight, Player::Black) => protocol::Piece::BlackKnight,
+        (Piece::Bishop, Player::Black) => protocol::Piece::BlackBishop,
+        (Piece::Queen,  Player::Black) => protocol::Piece::BlackQueen,
+        (Piece::King,   Player::Black) => protocol::Piece::BlackKing,
+
+        (Piece::Pawn,   Player::White) => protocol::Piece::WhitePawn,
+        (Piece::Rook,   Player::White) => protocol::Piece::WhiteRook,
+        (Piece::Knight, Player::White) => protocol::Piece::WhiteKnight,
+        (Piece::Bishop, Player::White) => protocol::Piece::WhiteBishop,
+        (Piece::Queen,  Player::White) => protocol::Piece::WhiteQueen,
+        (Piece::King,   Player::White) => protocol::Piece::WhiteKing,
+    }
+}
+
+/// Pieces a pawn may promote to, in the order they are offered
+pub const PROMOTIONS: [Piece; 4] = [
+    Piece::Queen,
+    Piece::Rook,
+    Piece::Bishop,
+    Piece::Knight,
+];
+
+/// Where to go when looking through a game
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum Seek {
+    Start,
+    Back,
+    Forward,
+    End,
+}
+
 pub trait Interface {
     
     fn get_state(&self) -> State;
+    /// The player using this screen, `None` if both are
+    fn get_player(&self) -> Option<Player>;
     fn update(&mut self);
-    fn get_piece_at(&self, x: u8, y: u8) -> Option<(Piece, Player)>;
-    fn select_piece(&mut self, at: (u8, u8));
-    fn play_move(&mut self, dst: (u8, u8));
+    fn get_piece_at(&self, at: Square) -> Option<(Piece, Player)>;
+    /// Squares the piece at `from` can legally move to, if known
+    fn get_destinations(&self, from: Square) -> Vec<Square>;
+    fn select_piece(&mut self, at: Square);
+    fn play_move(&mut self, dst: Square);
+    fn select_promotion(&mut self, piece: Piece);
+    /// Goes back to choosing where to move, instead of a promotion
+    fn cancel_promotion(&mut self) {}
+    /// Moves played so far, in Standard Algebraic Notation
+    fn get_history(&self) -> &[String];
+    /// The current position in Forsyth-Edwards Notation
+    fn get_fen(&self) -> String;
+    /// The position before the first move in the history, `None` until
+    /// it's known
+    fn get_start_position(&self) -> Option<fen::Position>;
+    /// Time left for `player`, `None` without a time control
+    fn get_clock(&self, player: Player) -> Option<std::time::Duration>;
+    /// Starts waiting for the opponent to come back after a disconnect
+    fn reconnect(&mut self) {}
+    /// Gives up the game for the player using this screen, or the one to
+    /// move if both are
+    fn resign(&mut self);
+    /// Offers a draw, or accepts the one the opponent offered
+    fn offer_draw(&mut self) {}
+    /// Turns down the draw the opponent offered
+    fn decline_draw(&mut self) {}
+    /// Who has a draw offer standing, if anyone
+    fn get_draw_offer(&self) -> Option<Player> {
+
+        None
+    }
+    /// Asks to take back our last move, or agrees to the opponent's
+    /// request. Layers without an opponent to ask undo right away.
+    fn request_takeback(&mut self) {}
+    /// Turns down the opponent's takeback request
+    fn decline_takeback(&mut self) {}
+    /// Who asked for a takeback, if anyone
+    fn get_takeback_request(&self) -> Option<Player> {
+
+        None
+    }
+    /// Steps through the positions of a replayed game
+    fn seek(&mut self, _to: Seek) {}
 }
 
 pub type Layer = Box<dyn Interface>;
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::game::Game;
+
+    #[test]
+    fn algebraic_round_trip() {
+
+        for at in Square::all() {
+            assert_eq!(Square::from_algebraic(&at.to_algebraic()), Some(at));
+        }
+        assert_eq!(Square::all().count(), 64);
+
+        assert_eq!(Square::from_algebraic("a1"), Square::new(0, 0));
+        assert_eq!(Square::from_algebraic("h8"), Square::new(7, 7));
+        assert_eq!(Square::from_algebraic("E2"), Square::new(1, 4));
+        assert_eq!(Square::new(1, 4).unwrap().to_string(), "e2");
+    }
+
+    #[test]
+    fn protocol_round_trip() {
+
+        for at in Square::all() {
+            let (x, y) = at.to_protocol();
+            assert_eq!(Square::from_protocol(x, y), Some(at));
+            assert_eq!((x, y), (at.file as usize, at.rank as usize));
+        }
+    }
+
+    #[test]
+    fn indices_round_trip() {
+
+        for at in Square::all() {
+            let (rank, file) = at.to_indices();
+            assert_eq!(Square::new(rank as u8, file as u8), Some(at));
+        }
+    }
+
+    #[test]
+    fn e2_everywhere() {
+
+        let e2 = Square::from_algebraic("e2").unwrap();
+        assert_eq!((e2.rank, e2.file), (1, 4));
+        assert_eq!(e2.to_protocol(), (4, 1));
+        assert_eq!(e2.to_indices(), (1, 4));
+
+        let game = Game::new();
+        let (x, y) = e2.to_protocol();
+        assert!(matches!(game.to_protocol()[y][x], protocol::Piece::WhitePawn));
+        assert_eq!(game.piece_at(e2), Some((Piece::Pawn, Player::White)));
+
+        // And the squares around it, so a swapped rank and file shows
+        let e1 = Square::from_algebraic("e1").unwrap();
+        let b5 = Square::from_algebraic("b5").unwrap();
+        let (x, y) = e1.to_protocol();
+        assert!(matches!(game.to_protocol()[y][x], protocol::Piece::WhiteKing));
+        assert_eq!(game.piece_at(e1), Some((Piece::King, Player::White)));
+        assert_eq!(game.piece_at(b5), None);
+    }
+
+    #[test]
+    fn rejects_off_board() {
+
+        assert_eq!(Square::from_algebraic("i1"), None);
+        assert_eq!(Square::from_algebraic("a9"), None);
+        assert_eq!(Square::from_algebraic("a0"), None);
+        assert_eq!(Square::from_algebraic("e"), None);
+        assert_eq!(Square::from_algebraic("e22"), None);
+        assert_eq!(Square::from_algebraic(""), None);
+
+        assert_eq!(Square::from_protocol(8, 0), None);
+        assert_eq!(Square::from_protocol(0, 8), None);
+        assert_eq!(Square::new(8, 0), None);
+        assert_eq!(Square::new(0, 8), None);
+    }
+}
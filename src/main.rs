@@ -1,58 +1,106 @@
 
+mod cli;
 mod server;
+mod headless;
+mod referee;
+mod door;
+mod lobby;
 mod client;
+mod spectator;
+mod local;
+mod engine;
+mod ai;
+mod search;
+mod game;
+mod fen;
+mod clock;
+mod draw;
+mod takeback;
+mod extensions;
+mod san;
 mod logic;
 mod app;
+mod pgn;
+mod replay;
 mod tcp_handler;
 
 use server::Server;
 use client::Client;
-use std::env;
+use spectator::Spectator;
+use local::Local;
+use engine::Engine;
+use ai::Ai;
+use replay::Replay;
+use cli::{ Cli, Command, };
+use clap::Parser;
+use std::net::SocketAddr;
 use std::process;
-
-const PORT: &str = "8384";
+use std::time::Duration;
 
 fn main() {
 
-    let args: Vec<_> = env::args().collect();
-    let name = args[0].clone();
+    let cli = Cli::parse();
+
+    let layer = match cli.command {
+
+        Command::Serve { fen, time, headless, lobby, bind, port, } => {
+
+            let addr = SocketAddr::new(bind, port);
 
-    let layer = match args.len() {
+            // Hosts games until stopped, each from the same position
+            if lobby {
+                lobby::run(addr, fen, time, cli.pgn_dir);
+                return;
+            }
 
-        1 => err_exit(name, format!("Missing option")),
+            // Referees two clients, no window needed
+            if headless {
+                headless::run(addr, fen, time, cli.pgn_dir);
+                return;
+            }
 
-        _ => match args[1].as_str() {
+            Server::new(addr, fen, time)
+        },
 
-            "serve" => Server::new(PORT.to_string()),
+        Command::Local => Local::new(),
 
-            "connect" => {
+        Command::Connect { addr, color, new, game, } => {
 
-                if args.len() == 3 {
-                    Client::new(format!("{}:{}", args[2], PORT))
-                } else {
-                    err_exit(name, "Missing address".to_string())
-                }
-            },
+            let entry = match (new, game) {
+                (true, _) => lobby::Entry::New,
+                (_, Some(id)) => lobby::Entry::Join(id),
+                _ => lobby::Entry::Any,
+            };
 
-            _ => err_exit(name, format!("Unknown option: {}", args[1])),
+            Client::new(addr.0, color.player(), entry)
         },
-    };
 
-    app::run(layer);    
-}
+        Command::Spectate { addr, game, } => Spectator::new(&addr.0, game),
 
-fn usage(name: String) {
+        Command::Lobby { addr, } => {
 
-    println!("
-        Usage:
-            {0} serve           Start server.
-            {0} connect <addr>  Connect to server at address <addr>.
-    ", name);
-}
+            if let Err(e) = lobby::list(&addr.0) {
+                println!("Could not reach lobby: {}", e);
+                process::exit(1);
+            }
+            return;
+        },
+
+        Command::Engine { path, movetime, depth, } => Engine::new(path, engine::Limits { movetime, depth, }),
+
+        Command::Ai { depth, time, } => {
+
+            let mut budget = search::Budget { depth, ..Default::default() };
+            if let Some(time) = time {
+                budget.time = Some(Duration::from_millis(time));
+            }
 
-fn err_exit(name: String, msg: String) -> ! {
+            Ai::new(budget)
+        },
+
+        // Counted from 1 on the command line
+        Command::Replay { file, game, } => Replay::new(file, game - 1),
+    };
 
-    println!("ERROR: {}", msg);
-    usage(name);
-    process::exit(1)
+    app::run(layer, cli.pgn_dir);
 }
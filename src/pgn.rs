@@ -0,0 +1,326 @@
+use chess_network_protocol as protocol;
+
+use crate::logic;
+use crate::fen;
+
+use std::fs::{ self, OpenOptions, };
+use std::io::{ self, Write, };
+use std::path::{ Path, PathBuf, };
+use std::time::{ SystemTime, UNIX_EPOCH, };
+
+// Export format keeps lines below this
+const LINE_WIDTH: usize = 80;
+
+/// One game read from a PGN file
+pub struct Entry {
+    pub tags:  Vec<(String, String)>,
+    /// In SAN, without move numbers, comments or variations
+    pub moves: Vec<String>,
+}
+
+impl Entry {
+
+    pub fn tag(&self, name: &str) -> &str {
+
+        self.tags.iter()
+            .find(|(tag, _)| tag == name)
+            .map_or("?", |(_, value)| value.as_str())
+    }
+}
+
+/// Reads every game in a PGN file
+pub fn parse(text: &str) -> Vec<Entry> {
+
+    let mut entries = Vec::new();
+    let mut tags = Vec::new();
+    let mut movetext = String::new();
+
+    for line in text.lines() {
+
+        let line = line.trim();
+        if line.starts_with('[') {
+
+            // Tags after moves start the next game
+            if !movetext.trim().is_empty() {
+                entries.push(Entry { tags, moves: parse_moves(&movetext), });
+                tags = Vec::new();
+                movetext.clear();
+            }
+
+            if let Some(tag) = parse_tag(line) {
+                tags.push(tag);
+            }
+        } else {
+            movetext += line;
+            movetext.push('\n');
+        }
+    }
+
+    if !tags.is_empty() || !movetext.trim().is_empty() {
+        entries.push(Entry { tags, moves: parse_moves(&movetext), });
+    }
+
+    entries
+}
+
+/// Reads `[Name "value"]`
+fn parse_tag(line: &str) -> Option<(String, String)> {
+
+    let inner = line.strip_prefix('[')?.strip_suffix(']')?;
+    let (name, value) = inner.split_once(' ')?;
+    let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+
+    Some((name.to_string(), value.replace("\\\"", "\"").replace("\\\\", "\\")))
+}
+
+fn parse_moves(movetext: &str) -> Vec<String> {
+
+    // Drop comments and variations first
+    let mut plain = String::new();
+    let mut depth: u32 = 0;
+    let mut chars = movetext.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => for c in chars.by_ref() {
+                if c == '}' {
+                    break;
+                }
+            },
+            ';' => for c in chars.by_ref() {
+                if c == '\n' {
+                    break;
+                }
+            },
+            '(' => depth += 1,
+            // A stray one doesn't hide the rest of the game
+            ')' => depth = depth.saturating_sub(1),
+            c if depth == 0 => plain.push(c),
+            _ => (),
+        }
+    }
+
+    plain.split_whitespace()
+        .filter(|token| !matches!(*token, "1-0" | "0-1" | "1/2-1/2" | "*"))
+        // Move numbers may be glued to the move, like "1.e4"
+        .map(|token| match token.rfind('.') {
+            Some(i) => &token[i + 1..],
+            None => token,
+        })
+        .filter(|token| !token.is_empty() && !token.starts_with('$'))
+        .map(|token| token.to_string())
+        .collect()
+}
+
+/// The Result tag and game termination marker for a game state
+pub fn result(joever: protocol::Joever) -> &'static str {
+
+    match joever {
+        protocol::Joever::White => "1-0",
+        protocol::Joever::Black => "0-1",
+        protocol::Joever::Draw  => "1/2-1/2",
+        _ => "*",
+    }
+}
+
+/// A whole game with the Seven Tag Roster, `history` in SAN. Games from
+/// `start` get SetUp and FEN tags unless it's the usual position.
+pub fn write(
+    start: Option<&fen::Position>,
+    history: &[String],
+    white: &str,
+    black: &str,
+    joever: protocol::Joever,
+) -> String {
+
+    let (year, month, day, _) = utc_now();
+    let result = result(joever);
+
+    let mut tags = vec![
+        ("Event",  "Casual game".to_string()),
+        ("Site",   "?".to_string()),
+        ("Date",   format!("{:04}.{:02}.{:02}", year, month, day)),
+        ("Round",  "-".to_string()),
+        ("White",  white.to_string()),
+        ("Black",  black.to_string()),
+        ("Result", result.to_string()),
+    ];
+    let start = start.map(|start| (start, fen::write(start))).filter(|(_, fen)| fen != fen::START);
+    if let Some((_, fen)) = &start {
+        tags.push(("SetUp", "1".to_string()));
+        tags.push(("FEN", fen.clone()));
+    }
+
+    let mut pgn = String::new();
+    for (tag, value) in tags {
+        pgn += &format!("[{} \"{}\"]\n", tag, escape(&value));
+    }
+    pgn.push('\n');
+
+    // Counting from where the game started, black may have the first move
+    let (mut fullmove, black_first) = match start {
+        Some((start, _)) => (start.fullmove as usize, start.to_move == logic::Player::Black),
+        None => (1, false),
+    };
+
+    let mut tokens = Vec::new();
+    let mut moves = history.iter();
+    if black_first {
+        if let Some(mov) = moves.next() {
+            tokens.push(format!("{}...", fullmove));
+            tokens.push(mov.clone());
+        }
+        fullmove += 1;
+    }
+    for (i, mov) in moves.enumerate() {
+        if i % 2 == 0 {
+            tokens.push(format!("{}.", fullmove + i / 2));
+        }
+        tokens.push(mov.clone());
+    }
+    tokens.push(result.to_string());
+
+    let mut line = String::new();
+    for token in tokens {
+        if !line.is_empty() && line.len() + 1 + token.len() > LINE_WIDTH {
+            pgn += &line;
+            pgn.push('\n');
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line += &token;
+    }
+    pgn += &line;
+    pgn.push('\n');
+
+    pgn
+}
+
+/// Writes the game to a new file in `dir`, named after the current time
+pub fn save(
+    dir: &Path,
+    start: Option<&fen::Position>,
+    history: &[String],
+    white: &str,
+    black: &str,
+    joever: protocol::Joever,
+) -> io::Result<PathBuf> {
+
+    let (year, month, day, seconds) = utc_now();
+    let stem = format!(
+        "{:04}-{:02}-{:02}-{:02}{:02}{:02}",
+        year, month, day,
+        seconds / 3600, seconds / 60 % 60, seconds % 60,
+    );
+    let pgn = write(start, history, white, black, joever);
+
+    fs::create_dir_all(dir)?;
+
+    // Games saved in the same second get a number, none is overwritten
+    let mut number = 1;
+    loop {
+        let name = match number {
+            1 => format!("{}.pgn", stem),
+            _ => format!("{}-{}.pgn", stem, number),
+        };
+        let path = dir.join(name);
+
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                file.write_all(pgn.as_bytes())?;
+                return Ok(path);
+            },
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => number += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn escape(value: &str) -> String {
+
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Year, month, day and seconds into the day
+fn utc_now() -> (u64, u64, u64, u64) {
+
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+
+    // Days to civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let z = secs / 86400 + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day, secs % 86400)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    use std::process;
+
+    #[test]
+    fn skips_comments_and_variations() {
+
+        let moves = parse_moves("1. e4 {best by test} e5 (1... c5 2. Nf3 (2. c3)) 2. Nf3 ; king's knight\n2... Nc6 $1 1-0");
+        assert_eq!(moves, ["e4", "e5", "Nf3", "Nc6"]);
+
+        // Unbalanced parentheses
+        assert_eq!(parse_moves("1. e4 ) e5 2. Nf3"), ["e4", "e5", "Nf3"]);
+        assert_eq!(parse_moves("1. e4 e5 (1... c5"), ["e4", "e5"]);
+    }
+
+    #[test]
+    fn usual_start_has_no_fen() {
+
+        let start = fen::parse(fen::START).unwrap();
+        let history = ["e4".to_string(), "e5".to_string()];
+        let pgn = write(Some(&start), &history, "A", "B", protocol::Joever::Ongoing);
+
+        assert!(!pgn.contains("[SetUp"));
+        assert!(!pgn.contains("[FEN"));
+        assert!(pgn.ends_with("\n1. e4 e5 *\n"));
+    }
+
+    #[test]
+    fn setup_and_fen_tags() {
+
+        let fen = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1";
+        let start = fen::parse(fen).unwrap();
+        let history = ["e5".to_string(), "Nf3".to_string(), "Nc6".to_string()];
+        let pgn = write(Some(&start), &history, "A", "B", protocol::Joever::Ongoing);
+
+        assert!(pgn.contains("[SetUp \"1\"]\n"));
+        assert!(pgn.contains(&format!("[FEN \"{}\"]\n", fen)));
+        // Black moves first, so its move gets the number
+        assert!(pgn.ends_with("\n1... e5 2. Nf3 Nc6 *\n"));
+
+        let entries = parse(&pgn);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].tag("FEN"), fen);
+        assert_eq!(entries[0].moves, history);
+    }
+
+    #[test]
+    fn saving_never_overwrites() {
+
+        let dir = std::env::temp_dir().join(format!("pgn-test-{}", process::id()));
+        let history = ["e4".to_string()];
+        let first = save(&dir, None, &history, "A", "B", protocol::Joever::Ongoing).unwrap();
+        let second = save(&dir, None, &history, "A", "B", protocol::Joever::Ongoing).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_ne!(first, second);
+    }
+}
@@ -0,0 +1,463 @@
+use chess_network_protocol::{
+    self as protocol,
+    ClientToServer as Cts,
+    ServerToClient as Stc,
+};
+
+use crate::logic::{ self, logic_to_proto, };
+use crate::clock::{ self, Clock, TimeControl, };
+use crate::takeback;
+use crate::game::Game;
+use crate::server::Server;
+use crate::tcp_handler::{ self as tcp, TcpHandler, };
+
+use std::net::{ SocketAddr, TcpStream, };
+
+type Board = [[protocol::Piece; 8]; 8];
+
+/// Something everyone in the game is told. Messages are built again for
+/// each of them.
+pub enum Announcement {
+    Moved {
+        board:     Board,
+        moves:     Vec<protocol::Move>,
+        joever:    protocol::Joever,
+        move_made: protocol::Move,
+    },
+    /// The loser's clock ran out
+    OutOfTime { board: Board, winner: logic::Player, },
+    Resigned { board: Board, winner: logic::Player, },
+    /// Moves were taken back, leaving this position
+    TakenBack { board: Board, moves: Vec<protocol::Move>, },
+}
+
+impl Announcement {
+
+    pub fn message(&self) -> Stc {
+
+        match self {
+            Announcement::Moved { board, moves, joever, move_made, } => Stc::State {
+                board: *board,
+                moves: moves.clone(),
+                joever: *joever,
+                move_made: *move_made,
+            },
+            Announcement::OutOfTime { board, winner, } => Stc::Error {
+                board: *board,
+                moves: Vec::new(),
+                joever: winner_joever(*winner),
+                message: clock::OUT_OF_TIME.to_string(),
+            },
+            Announcement::Resigned { board, winner, } => Stc::Resigned {
+                board: *board,
+                joever: winner_joever(*winner),
+            },
+            Announcement::TakenBack { board, moves, } => Stc::Error {
+                board: *board,
+                moves: moves.clone(),
+                joever: protocol::Joever::Ongoing,
+                message: takeback::TAKEN_BACK.to_string(),
+            },
+        }
+    }
+}
+
+/// A move the referee let through
+pub struct Played {
+    pub san:          String,
+    /// How the move ended the game, `None` if it goes on
+    pub outcome:      Option<logic::State>,
+    pub announcement: Announcement,
+}
+
+/// What came of offering a draw
+pub enum Offer {
+    /// The same side already offered
+    Repeated,
+    Made,
+    /// The other side had offered, the game is drawn
+    Accepted,
+}
+
+/// What came of asking for a takeback
+pub enum Takeback {
+    /// The same side already asked
+    Repeated,
+    /// The side asking has no move on the board
+    NothingToTakeBack,
+    Requested,
+    /// The other side had asked, their move is gone
+    TakenBack(Announcement),
+}
+
+/// The rules a server enforces between two players, whoever they are.
+/// Spectators are kept posted here, telling the players is up to the
+/// server.
+pub struct Referee {
+
+    game: Game,
+    history: Vec<String>,
+    // The game before each move, for taking them back
+    undo: Vec<Game>,
+    clock: Option<Clock>,
+    draw_offer: Option<logic::Player>,
+    takeback: Option<logic::Player>,
+    // Get every position, never listened to
+    spectators: Vec<TcpHandler<Cts, Stc>>,
+}
+
+impl Referee {
+
+    /// The clock waits for `start_clock`
+    pub fn new(game: Game, control: Option<TimeControl>) -> Self {
+
+        Self {
+            game,
+            history: Vec::new(),
+            undo: Vec::new(),
+            clock: control.map(Clock::new),
+            draw_offer: None,
+            takeback: None,
+            spectators: Vec::new(),
+        }
+    }
+
+    pub fn game(&self) -> &Game {
+
+        &self.game
+    }
+
+    /// Moves played so far, in SAN
+    pub fn history(&self) -> &[String] {
+
+        &self.history
+    }
+
+    pub fn clock(&self) -> Option<&Clock> {
+
+        self.clock.as_ref()
+    }
+
+    pub fn draw_offer(&self) -> Option<logic::Player> {
+
+        self.draw_offer
+    }
+
+    pub fn takeback(&self) -> Option<logic::Player> {
+
+        self.takeback
+    }
+
+    /// Starts the clock of the player to move
+    pub fn start_clock(&mut self) {
+
+        let to_move = self.game.to_move();
+        if let Some(clock) = &mut self.clock {
+            clock.start(to_move);
+        }
+    }
+
+    /// Stops both clocks, `start_clock` picks up where they left off
+    pub fn stop_clock(&mut self) {
+
+        if let Some(clock) = &mut self.clock {
+            clock.stop();
+        }
+    }
+
+    /// Sends a spectator the position and keeps them posted from now on
+    pub fn welcome_spectator(&mut self, stream: TcpStream, addr: SocketAddr) {
+
+        let control = self.clock.as_ref().map(Clock::control);
+        let handshake = Server::send_handshake(&stream, &self.game, self.game.to_move(), control)
+            .and_then(|_| stream.set_read_timeout(None).map_err(tcp::Error::Io));
+        if let Err(e) = handshake {
+            println!("Handshake with spectator {} failed: {}", addr, e);
+            return;
+        }
+        println!("Spectator joined ({})", addr);
+
+        self.spectators.push(TcpHandler::new(stream));
+    }
+
+    /// Spectators that can't be written to have left
+    fn tell_spectators(&mut self, announcement: &Announcement) {
+
+        self.spectators.retain(|spectator| spectator.write(announcement.message()).is_ok());
+    }
+
+    /// Tells a player something the protocol has no message for, as an
+    /// error with the current position
+    pub fn notice(&self, message: &str) -> Stc {
+
+        Stc::Error {
+            board: self.game.to_protocol(),
+            moves: self.game.to_protocol_moves(self.game.to_move()),
+            joever: protocol::Joever::Ongoing,
+            message: message.to_string(),
+        }
+    }
+
+    /// Offers a draw, or accepts the offer it answers
+    pub fn draw_message(&self) -> Stc {
+
+        Stc::Draw {
+            board: self.game.to_protocol(),
+            moves: self.game.to_protocol_moves(self.game.to_move()),
+        }
+    }
+
+    /// Plays `mov` for `player`, `Err` says why it can't be played
+    pub fn make_move(&mut self, player: logic::Player, mov: protocol::Move) -> Result<Played, &'static str> {
+
+        if player != self.game.to_move() {
+            return Err("Not your turn");
+        }
+
+        let (from, to) = match (
+            logic::Square::from_protocol(mov.start_x, mov.start_y),
+            logic::Square::from_protocol(mov.end_x, mov.end_y),
+        ) {
+            (Some(from), Some(to)) => (from, to),
+            _ => return Err("Move is off the board"),
+        };
+
+        let before = self.game.clone();
+        let promotion = self.game.requested_promotion(from, to, mov.promotion)
+            .map_err(|()| "Illegal move")?;
+        let san = self.game.play(logic::Move { from, to, promotion, })
+            .ok_or("Illegal move")?;
+        self.history.push(san.clone());
+        self.undo.push(before);
+
+        // Moving instead of answering turns the offer down
+        if self.draw_offer == Some(player.other()) {
+            self.draw_offer = None;
+        }
+        if self.takeback == Some(player.other()) {
+            self.takeback = None;
+        }
+
+        let outcome = self.game.outcome();
+        let joever = outcome_joever(outcome);
+        if let Some(clock) = &mut self.clock {
+            if matches!(joever, protocol::Joever::Ongoing) {
+                clock.press(player);
+            } else {
+                clock.stop();
+            }
+        }
+
+        let promotion = match promotion {
+            Some(piece) => logic_to_proto(piece, player),
+            None => protocol::Piece::None,
+        };
+        let announcement = Announcement::Moved {
+            board: self.game.to_protocol(),
+            moves: self.game.to_protocol_moves(player.other()),
+            joever,
+            move_made: protocol::Move { promotion, ..mov },
+        };
+        self.tell_spectators(&announcement);
+
+        Ok(Played { san, outcome, announcement, })
+    }
+
+    /// Ends the game if someone's time ran out, returns who that was
+    pub fn check_clock(&mut self) -> Option<(logic::Player, Announcement)> {
+
+        let loser = self.clock.as_ref().and_then(Clock::flagged)?;
+        self.stop_clock();
+
+        let announcement = Announcement::OutOfTime {
+            board: self.game.to_protocol(),
+            winner: loser.other(),
+        };
+        self.tell_spectators(&announcement);
+
+        Some((loser, announcement))
+    }
+
+    pub fn resign(&mut self, loser: logic::Player) -> Announcement {
+
+        self.stop_clock();
+
+        let announcement = Announcement::Resigned {
+            board: self.game.to_protocol(),
+            winner: loser.other(),
+        };
+        self.tell_spectators(&announcement);
+
+        announcement
+    }
+
+    /// `from` either offers a draw or accepts the other side's offer
+    pub fn offer_draw(&mut self, from: logic::Player) -> Offer {
+
+        if self.draw_offer == Some(from) {
+            return Offer::Repeated;
+        }
+
+        if self.draw_offer.is_none() {
+            self.draw_offer = Some(from);
+            return Offer::Made;
+        }
+
+        self.stop_clock();
+        self.draw_offer = None;
+
+        // Spectators never see offers, to them this ends the game
+        let board = self.game.to_protocol();
+        self.spectators.retain(|spectator| spectator.write(Stc::Draw { board, moves: Vec::new(), }).is_ok());
+
+        Offer::Accepted
+    }
+
+    /// Turns down the other side's offer, returns whether there was one
+    pub fn decline_draw(&mut self, by: logic::Player) -> bool {
+
+        if self.draw_offer != Some(by.other()) {
+            return false;
+        }
+        self.draw_offer = None;
+        true
+    }
+
+    /// `from` either asks for a takeback or agrees to the other side's
+    /// request
+    pub fn request_takeback(&mut self, from: logic::Player) -> Takeback {
+
+        if self.takeback == Some(from) {
+            return Takeback::Repeated;
+        }
+
+        if let Some(requester) = self.takeback {
+            return Takeback::TakenBack(self.take_back(requester));
+        }
+
+        if takeback::plies(from, self.game.to_move()) > self.undo.len() {
+            return Takeback::NothingToTakeBack;
+        }
+
+        self.takeback = Some(from);
+        Takeback::Requested
+    }
+
+    /// Turns down the other side's request, returns whether there was one
+    pub fn decline_takeback(&mut self, by: logic::Player) -> bool {
+
+        if self.takeback != Some(by.other()) {
+            return false;
+        }
+        self.takeback = None;
+        true
+    }
+
+    /// Goes back to before the last move of `requester`
+    fn take_back(&mut self, requester: logic::Player) -> Announcement {
+
+        let plies = takeback::plies(requester, self.game.to_move());
+        let at = self.undo.len() - plies;
+        self.game = self.undo[at].clone();
+        self.undo.truncate(at);
+        self.history.truncate(at);
+        self.takeback = None;
+        self.draw_offer = None;
+
+        self.stop_clock();
+        self.start_clock();
+
+        let announcement = Announcement::TakenBack {
+            board: self.game.to_protocol(),
+            moves: self.game.to_protocol_moves(self.game.to_move()),
+        };
+        self.tell_spectators(&announcement);
+
+        announcement
+    }
+}
+
+/// The result to send after a move, from what it led to
+pub fn outcome_joever(outcome: Option<logic::State>) -> protocol::Joever {
+
+    match outcome {
+        Some(logic::State::CheckMate(winner)) => winner_joever(winner),
+        Some(_) => protocol::Joever::Draw,
+        None => protocol::Joever::Ongoing,
+    }
+}
+
+pub fn winner_joever(winner: logic::Player) -> protocol::Joever {
+
+    match winner {
+        logic::Player::White => protocol::Joever::White,
+        logic::Player::Black => protocol::Joever::Black,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    use logic::Player::{ Black, White, };
+
+    fn mov(from: &str, to: &str) -> protocol::Move {
+
+        let (start_x, start_y) = logic::Square::from_algebraic(from).unwrap().to_protocol();
+        let (end_x, end_y) = logic::Square::from_algebraic(to).unwrap().to_protocol();
+        protocol::Move { start_x, start_y, end_x, end_y, promotion: protocol::Piece::None, }
+    }
+
+    #[test]
+    fn moves_in_turn() {
+
+        let mut referee = Referee::new(Game::new(), None);
+        assert!(matches!(referee.make_move(Black, mov("e7", "e5")), Err("Not your turn")));
+        assert!(matches!(referee.make_move(White, mov("e2", "e5")), Err("Illegal move")));
+
+        let played = referee.make_move(White, mov("e2", "e4")).ok().unwrap();
+        assert_eq!(played.san, "e4");
+        assert!(played.outcome.is_none());
+        assert_eq!(referee.history(), ["e4"]);
+    }
+
+    #[test]
+    fn draw_offers() {
+
+        let mut referee = Referee::new(Game::new(), None);
+        assert!(matches!(referee.offer_draw(White), Offer::Made));
+        assert!(matches!(referee.offer_draw(White), Offer::Repeated));
+        assert!(!referee.decline_draw(White));
+        assert!(matches!(referee.offer_draw(Black), Offer::Accepted));
+        assert_eq!(referee.draw_offer(), None);
+    }
+
+    #[test]
+    fn moving_turns_offers_down() {
+
+        let mut referee = Referee::new(Game::new(), None);
+        referee.make_move(White, mov("e2", "e4")).ok().unwrap();
+        referee.offer_draw(White);
+        referee.request_takeback(White);
+
+        referee.make_move(Black, mov("e7", "e5")).ok().unwrap();
+        assert_eq!(referee.draw_offer(), None);
+        assert_eq!(referee.takeback(), None);
+    }
+
+    #[test]
+    fn takebacks() {
+
+        let mut referee = Referee::new(Game::new(), None);
+        assert!(matches!(referee.request_takeback(White), Takeback::NothingToTakeBack));
+
+        referee.make_move(White, mov("e2", "e4")).ok().unwrap();
+        assert!(matches!(referee.request_takeback(White), Takeback::Requested));
+        assert!(matches!(referee.request_takeback(White), Takeback::Repeated));
+        assert!(matches!(referee.request_takeback(Black), Takeback::TakenBack(_)));
+
+        assert!(referee.history().is_empty());
+        assert_eq!(referee.game().to_move(), White);
+    }
+}
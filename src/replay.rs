@@ -0,0 +1,176 @@
+use crate::logic;
+use crate::game::Game;
+use crate::fen;
+use crate::pgn;
+
+use std::fs;
+use std::process;
+use std::time::Duration;
+
+/// Steps through a game loaded from a PGN file
+pub struct Replay {
+
+    // The game after each move, starting with the initial position
+    games: Vec<Game>,
+    history: Vec<String>,
+    // How many moves are shown
+    ply: usize,
+}
+
+impl Replay {
+
+    /// Loads game `index` of the file, counting from 0
+    pub fn new(path: String, index: usize) -> logic::Layer {
+
+        match Self::load(&path, index) {
+            Ok(replay) => Box::new(replay),
+            Err(e) => {
+                println!("Could not replay {}: {}", path, e);
+                process::exit(1);
+            },
+        }
+    }
+
+    fn load(path: &str, index: usize) -> Result<Self, String> {
+
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let entries = pgn::parse(&text);
+
+        if entries.len() > 1 {
+            println!("{} contains {} games:", path, entries.len());
+            for (i, entry) in entries.iter().enumerate() {
+                println!("    {}: {} - {} ({}, {})",
+                    i + 1,
+                    entry.tag("White"),
+                    entry.tag("Black"),
+                    entry.tag("Date"),
+                    entry.tag("Result"),
+                );
+            }
+        }
+
+        let entry = match entries.get(index) {
+            Some(entry) => entry,
+            None => return Err(format!("there is no game number {}", index + 1)),
+        };
+        println!("Replaying {} - {}", entry.tag("White"), entry.tag("Black"));
+
+        // Games that don't start from the usual position say where they do
+        let mut game = match entry.tags.iter().find(|(tag, _)| tag == "FEN") {
+            Some((_, fen)) => match fen::parse(fen) {
+                Ok(position) => Game::from_position(&position),
+                Err(e) => return Err(format!("the game has an invalid FEN tag: {}", e)),
+            },
+            None => Game::new(),
+        };
+        let mut games = vec![game.clone()];
+        let mut history = Vec::new();
+
+        for san in &entry.moves {
+
+            let played = game.find_move(game.to_move(), san).and_then(|mov| game.play(mov));
+            match played {
+                Some(san) => history.push(san),
+                None => {
+                    println!("Move {} ({}) is illegal, stopping there", history.len() / 2 + 1, san);
+                    break;
+                },
+            }
+
+            games.push(game.clone());
+        }
+
+        Ok(Self {
+            games,
+            history,
+            ply: 0,
+        })
+    }
+}
+
+impl logic::Interface for Replay {
+
+    fn get_state(&self) -> logic::State {
+
+        logic::State::Replay
+    }
+
+    fn get_player(&self) -> Option<logic::Player> {
+
+        None
+    }
+
+    fn update(&mut self) {}
+
+    fn get_piece_at(&self, at: logic::Square) -> Option<(logic::Piece, logic::Player)> {
+
+        self.games[self.ply].piece_at(at)
+    }
+
+    fn get_destinations(&self, _from: logic::Square) -> Vec<logic::Square> {
+
+        Vec::new()
+    }
+
+    fn select_piece(&mut self, _at: logic::Square) {}
+
+    fn play_move(&mut self, _dst: logic::Square) {}
+
+    fn select_promotion(&mut self, _piece: logic::Piece) {}
+
+    fn get_history(&self) -> &[String] {
+
+        &self.history[..self.ply]
+    }
+
+    fn get_fen(&self) -> String {
+
+        self.games[self.ply].to_fen()
+    }
+
+    fn get_start_position(&self) -> Option<fen::Position> {
+
+        Some(self.games[0].start_position())
+    }
+
+    fn get_clock(&self, _player: logic::Player) -> Option<Duration> {
+
+        None
+    }
+
+    fn resign(&mut self) {}
+
+    fn seek(&mut self, to: logic::Seek) {
+
+        let last = self.history.len();
+        self.ply = match to {
+            logic::Seek::Start   => 0,
+            logic::Seek::Back    => self.ply.saturating_sub(1),
+            logic::Seek::Forward => (self.ply + 1).min(last),
+            logic::Seek::End     => last,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn bad_files_are_errors() {
+
+        let path = std::env::temp_dir().join(format!("replay-test-{}.pgn", process::id()));
+        fs::write(&path, "[FEN \"not a position\"]\n\n1. e4 *\n").unwrap();
+        let path = path.to_str().unwrap();
+
+        let no_game = Replay::load(path, 1).err();
+        let bad_fen = Replay::load(path, 0).err();
+        let _ = fs::remove_file(path);
+        let missing = Replay::load(path, 0).err();
+
+        assert_eq!(no_game.as_deref(), Some("there is no game number 2"));
+        assert!(bad_fen.unwrap().contains("invalid FEN"));
+        assert!(missing.is_some());
+    }
+}
@@ -0,0 +1,236 @@
+use crate::logic::{ self, Board, Piece, Player, Square, };
+
+/// Writes `mov` in Standard Algebraic Notation. `before` and `after` are
+/// the boards around the move, `mate` whether it ended the game.
+pub fn san(before: &Board, mov: logic::Move, after: &Board, mate: bool) -> String {
+
+    let (piece, player) = match piece_at(before, mov.from) {
+        Some(piece) => piece,
+        // Nothing to describe, fall back to coordinates
+        None => return format!("{}{}", mov.from, mov.to),
+    };
+
+    let mut san = String::new();
+    let cols = mov.to.file as i8 - mov.from.file as i8;
+
+    if piece == Piece::King && cols.abs() == 2 {
+        san += if cols > 0 { "O-O" } else { "O-O-O" };
+    } else {
+
+        // Pawns changing file always capture, even en passant
+        let capture = piece_at(before, mov.to).is_some()
+            || (piece == Piece::Pawn && cols != 0);
+
+        if piece == Piece::Pawn {
+            if capture {
+                san.push(file_char(mov.from));
+            }
+        } else {
+            san.push(letter(piece));
+            san += &disambiguation(before, mov, piece, player);
+        }
+
+        if capture {
+            san.push('x');
+        }
+        san += &mov.to.to_algebraic();
+
+        if let Some(promotion) = mov.promotion {
+            san.push('=');
+            san.push(letter(promotion));
+        }
+    }
+
+    if mate {
+        san.push('#');
+    } else if in_check(after, player.other()) {
+        san.push('+');
+    }
+
+    san
+}
+
+/// What a SAN move says about itself, the moving piece still has to be
+/// found on the board
+pub struct Parsed {
+    pub piece:     Piece,
+    pub from_file: Option<u8>,
+    pub from_rank: Option<u8>,
+    pub to:        Square,
+    pub promotion: Option<Piece>,
+}
+
+/// Reads a move like "Nbxd2+" or "O-O" played by `player`
+pub fn parse(san: &str, player: Player) -> Option<Parsed> {
+
+    let san = san.trim_end_matches(['+', '#', '!', '?']);
+    let back_rank = match player {
+        Player::White => 0,
+        Player::Black => 7,
+    };
+
+    let castle = |file| Some(Parsed {
+        piece:     Piece::King,
+        from_file: Some(4),
+        from_rank: Some(back_rank),
+        to:        Square { rank: back_rank, file, },
+        promotion: None,
+    });
+    // Zeros show up in some exports
+    match san {
+        "O-O" | "0-0" => return castle(6),
+        "O-O-O" | "0-0-0" => return castle(2),
+        _ => (),
+    }
+
+    let (san, promotion) = match san.split_once('=') {
+        Some((san, promotion)) => (san, Some(from_letter(promotion.chars().next()?)?)),
+        None => (san, None),
+    };
+
+    let mut chars: Vec<char> = san.chars().filter(|&c| c != 'x').collect();
+    let piece = match chars.first() {
+        Some(c) if c.is_ascii_uppercase() => {
+            let piece = from_letter(*c)?;
+            chars.remove(0);
+            piece
+        },
+        _ => Piece::Pawn,
+    };
+
+    // The destination is always last, anything before it disambiguates
+    if chars.len() < 2 {
+        return None;
+    }
+    let to: String = chars.split_off(chars.len() - 2).into_iter().collect();
+    let to = Square::from_algebraic(&to)?;
+
+    let mut from_file = None;
+    let mut from_rank = None;
+    for c in chars {
+        match c {
+            'a'..='h' => from_file = Some(c as u8 - b'a'),
+            '1'..='8' => from_rank = Some(c as u8 - b'1'),
+            _ => return None,
+        }
+    }
+
+    Some(Parsed { piece, from_file, from_rank, to, promotion, })
+}
+
+/// The upper case letter SAN uses for a piece
+pub fn letter(piece: Piece) -> char {
+
+    match piece {
+        Piece::Pawn   => 'P',
+        Piece::Rook   => 'R',
+        Piece::Knight => 'N',
+        Piece::Bishop => 'B',
+        Piece::Queen  => 'Q',
+        Piece::King   => 'K',
+    }
+}
+
+pub fn from_letter(letter: char) -> Option<Piece> {
+
+    match letter {
+        'P' => Some(Piece::Pawn),
+        'R' => Some(Piece::Rook),
+        'N' => Some(Piece::Knight),
+        'B' => Some(Piece::Bishop),
+        'Q' => Some(Piece::Queen),
+        'K' => Some(Piece::King),
+        _ => None,
+    }
+}
+
+/// Whether the king of `player` is attacked
+pub fn in_check(board: &Board, player: Player) -> bool {
+
+    let king = Square::all().find(|&at| piece_at(board, at) == Some((Piece::King, player)));
+
+    match king {
+        Some(king) => Square::all().any(|at|
+            matches!(piece_at(board, at), Some((_, owner)) if owner != player)
+            && attacks(board, at, king)
+        ),
+        None => false,
+    }
+}
+
+/// Whether the piece on `from` attacks `to`, ignoring pins
+pub fn attacks(board: &Board, from: Square, to: Square) -> bool {
+
+    let (piece, player) = match piece_at(board, from) {
+        Some(piece) => piece,
+        None => return false,
+    };
+
+    let rows = to.rank as i8 - from.rank as i8;
+    let cols = to.file as i8 - from.file as i8;
+    let forward = match player {
+        Player::White => 1,
+        Player::Black => -1,
+    };
+
+    if (rows, cols) == (0, 0) {
+        return false;
+    }
+
+    let straight = rows == 0 || cols == 0;
+    let diagonal = rows.abs() == cols.abs();
+
+    match piece {
+        Piece::Pawn   => rows == forward && cols.abs() == 1,
+        Piece::Knight => matches!((rows.abs(), cols.abs()), (1, 2) | (2, 1)),
+        Piece::Bishop => diagonal && clear(board, from, rows, cols),
+        Piece::Rook   => straight && clear(board, from, rows, cols),
+        Piece::Queen  => (straight || diagonal) && clear(board, from, rows, cols),
+        Piece::King   => rows.abs() <= 1 && cols.abs() <= 1,
+    }
+}
+
+pub fn piece_at(board: &Board, at: Square) -> Option<(Piece, Player)> {
+
+    let (rank, file) = at.to_indices();
+    board[rank][file]
+}
+
+/// Whether the squares strictly between `from` and `from + (rows, cols)`
+/// are empty, along a straight or diagonal line
+fn clear(board: &Board, from: Square, rows: i8, cols: i8) -> bool {
+
+    let steps = rows.abs().max(cols.abs());
+
+    (1..steps).all(|i| {
+        let rank = (from.rank as i8 + rows.signum() * i) as u8;
+        let file = (from.file as i8 + cols.signum() * i) as u8;
+        piece_at(board, Square { rank, file, }).is_none()
+    })
+}
+
+/// The file, rank or square needed to tell the moving piece apart from
+/// others of its kind that could go to the same square
+fn disambiguation(board: &Board, mov: logic::Move, piece: Piece, player: Player) -> String {
+
+    let rivals: Vec<_> = Square::all()
+        .filter(|&at| at != mov.from)
+        .filter(|&at| piece_at(board, at) == Some((piece, player)))
+        .filter(|&at| attacks(board, at, mov.to))
+        .collect();
+
+    if rivals.is_empty() {
+        String::new()
+    } else if rivals.iter().all(|at| at.file != mov.from.file) {
+        file_char(mov.from).to_string()
+    } else if rivals.iter().all(|at| at.rank != mov.from.rank) {
+        (mov.from.rank + 1).to_string()
+    } else {
+        mov.from.to_algebraic()
+    }
+}
+
+fn file_char(at: Square) -> char {
+
+    (b'a' + at.file) as char
+}
@@ -0,0 +1,394 @@
+use crate::logic;
+use crate::game::{ Game, PositionKey, };
+
+use std::time::{ Duration, Instant, };
+
+// Larger than any evaluation, reduced by the distance to the mate
+const MATE: i32 = 1_000_000;
+
+/// How much effort to put into each move
+#[derive(Clone, Copy)]
+pub struct Budget {
+    pub depth: u32,
+    /// Deeper searches are abandoned once this runs out
+    pub time:  Option<Duration>,
+}
+
+impl Default for Budget {
+
+    fn default() -> Self {
+
+        Self {
+            depth: 4,
+            time:  Some(Duration::from_secs(2)),
+        }
+    }
+}
+
+/// Finds the best move for `player`, or `None` if there are no legal moves
+pub fn best_move(game: &Game, player: logic::Player, budget: Budget) -> Option<logic::Move> {
+
+    let start = Instant::now();
+    let mut moves = ordered_moves(game, player);
+    let mut best = *moves.first()?;
+
+    // The history is only looked at through the searcher's path
+    let root = game.without_history();
+    let mut searcher = Searcher {
+        deadline: None,
+        path: game.positions().to_vec(),
+    };
+
+    // Iterative deepening, so running out of time still leaves a move
+    for depth in 1..=budget.depth.max(1) {
+
+        // The first iteration always finishes
+        searcher.deadline = budget.time.filter(|_| depth > 1).map(|time| start + time);
+
+        let mov = match searcher.root(&root, player, &moves, depth) {
+            Some(mov) => mov,
+            None => break,
+        };
+        best = mov;
+
+        // Search the best move first next time
+        if let Some(i) = moves.iter().position(|m| *m == mov) {
+            moves[..=i].rotate_right(1);
+        }
+    }
+
+    Some(best)
+}
+
+/// Whether `player` takes a draw, only when it isn't a pawn or more ahead
+pub fn accepts_draw(game: &Game, player: logic::Player) -> bool {
+
+    evaluate(game, player) < value(logic::Piece::Pawn)
+}
+
+struct Searcher {
+    deadline: Option<Instant>,
+    // Every position from the start of the game to the one searched
+    path: Vec<PositionKey>,
+}
+
+impl Searcher {
+
+    fn out_of_time(&self) -> bool {
+
+        matches!(self.deadline, Some(deadline) if Instant::now() >= deadline)
+    }
+
+    /// Whether `key` came up before, since the last capture or pawn move
+    /// made it `halfmove` plies ago
+    fn repeats(&self, key: &PositionKey, halfmove: u32) -> bool {
+
+        let since = self.path.len().saturating_sub(halfmove as usize);
+        self.path[since..].contains(key)
+    }
+
+    fn root(
+        &mut self,
+        game: &Game,
+        player: logic::Player,
+        moves: &[logic::Move],
+        depth: u32,
+    ) -> Option<logic::Move> {
+
+        let mut alpha = -MATE;
+        let mut best = None;
+
+        for &mov in moves {
+
+            let score = match self.score_move(game, player, mov, depth, 0, alpha, MATE)? {
+                Some(score) => score,
+                None => continue,
+            };
+
+            if best.is_none() || score > alpha {
+                alpha = score;
+                best = Some(mov);
+            }
+        }
+
+        best
+    }
+
+    /// Scores `mov` from the point of view of `player`. The outer `None`
+    /// means time ran out, the inner that the backend refused the move.
+    #[allow(clippy::too_many_arguments)]
+    fn score_move(
+        &mut self,
+        game: &Game,
+        player: logic::Player,
+        mov: logic::Move,
+        depth: u32,
+        ply: i32,
+        alpha: i32,
+        beta: i32,
+    ) -> Option<Option<i32>> {
+
+        let mut next = game.clone();
+        if !next.try_move(mov.from, mov.to, mov.promotion) {
+            return Some(None);
+        }
+
+        if next.mate() {
+            return Some(Some(MATE - ply - 1));
+        }
+
+        // Going back to an earlier position is as good as a draw
+        let key = match next.positions().last() {
+            Some(key) if self.repeats(key, next.halfmove()) => return Some(Some(0)),
+            Some(key) => *key,
+            None => return Some(None),
+        };
+
+        self.path.push(key);
+        let score = self.negamax(&next, player.other(), depth - 1, ply + 1, -beta, -alpha);
+        self.path.pop();
+
+        Some(Some(-score?))
+    }
+
+    fn negamax(
+        &mut self,
+        game: &Game,
+        player: logic::Player,
+        depth: u32,
+        ply: i32,
+        mut alpha: i32,
+        beta: i32,
+    ) -> Option<i32> {
+
+        if self.out_of_time() {
+            return None;
+        }
+
+        if depth == 0 {
+            return Some(evaluate(game, player));
+        }
+
+        let moves = ordered_moves(game, player);
+
+        // Mates are caught when the move is made, so this is stalemate
+        if moves.is_empty() {
+            return Some(0);
+        }
+
+        for mov in moves {
+
+            let score = match self.score_move(game, player, mov, depth, ply, alpha, beta)? {
+                Some(score) => score,
+                None => continue,
+            };
+
+            if score >= beta {
+                return Some(beta);
+            }
+            if score > alpha {
+                alpha = score;
+            }
+        }
+
+        Some(alpha)
+    }
+}
+
+/// Legal moves with the most valuable captures first, which makes
+/// alpha-beta cut off sooner
+fn ordered_moves(game: &Game, player: logic::Player) -> Vec<logic::Move> {
+
+    let mut moves = game.legal_moves(player);
+
+    moves.sort_by_key(|mov| {
+        let captured = match game.piece_at(mov.to) {
+            Some((piece, _)) => value(piece),
+            None => 0,
+        };
+        let promoted = mov.promotion.map_or(0, value);
+        -(captured + promoted)
+    });
+
+    moves
+}
+
+/// Material and piece placement, from the point of view of `player`
+fn evaluate(game: &Game, player: logic::Player) -> i32 {
+
+    let mut score = 0;
+
+    for square in logic::Square::all() {
+
+        let (piece, owner) = match game.piece_at(square) {
+            Some(piece) => piece,
+            None => continue,
+        };
+
+        // Tables are written with the owner's back rank at the bottom
+        let row = match owner {
+            logic::Player::White => 7 - square.rank as usize,
+            logic::Player::Black => square.rank as usize,
+        };
+        let table = match piece {
+            logic::Piece::Pawn   => &PAWN_TABLE,
+            logic::Piece::Knight => &KNIGHT_TABLE,
+            logic::Piece::Bishop => &BISHOP_TABLE,
+            logic::Piece::Rook   => &ROOK_TABLE,
+            logic::Piece::Queen  => &QUEEN_TABLE,
+            logic::Piece::King   => &KING_TABLE,
+        };
+
+        let worth = value(piece) + table[row][square.file as usize];
+        score += if owner == player { worth } else { -worth };
+    }
+
+    score
+}
+
+fn value(piece: logic::Piece) -> i32 {
+
+    match piece {
+        logic::Piece::Pawn   => 100,
+        logic::Piece::Knight => 320,
+        logic::Piece::Bishop => 330,
+        logic::Piece::Rook   => 500,
+        logic::Piece::Queen  => 900,
+        // Both sides always have one
+        logic::Piece::King   => 0,
+    }
+}
+
+const PAWN_TABLE: [[i32; 8]; 8] = [
+    [  0,   0,   0,   0,   0,   0,   0,   0],
+    [ 50,  50,  50,  50,  50,  50,  50,  50],
+    [ 10,  10,  20,  30,  30,  20,  10,  10],
+    [  5,   5,  10,  25,  25,  10,   5,   5],
+    [  0,   0,   0,  20,  20,   0,   0,   0],
+    [  5,  -5, -10,   0,   0, -10,  -5,   5],
+    [  5,  10,  10, -20, -20,  10,  10,   5],
+    [  0,   0,   0,   0,   0,   0,   0,   0],
+];
+
+const KNIGHT_TABLE: [[i32; 8]; 8] = [
+    [-50, -40, -30, -30, -30, -30, -40, -50],
+    [-40, -20,   0,   0,   0,   0, -20, -40],
+    [-30,   0,  10,  15,  15,  10,   0, -30],
+    [-30,   5,  15,  20,  20,  15,   5, -30],
+    [-30,   0,  15,  20,  20,  15,   0, -30],
+    [-30,   5,  10,  15,  15,  10,   5, -30],
+    [-40, -20,   0,   5,   5,   0, -20, -40],
+    [-50, -40, -30, -30, -30, -30, -40, -50],
+];
+
+const BISHOP_TABLE: [[i32; 8]; 8] = [
+    [-20, -10, -10, -10, -10, -10, -10, -20],
+    [-10,   0,   0,   0,   0,   0,   0, -10],
+    [-10,   0,   5,  10,  10,   5,   0, -10],
+    [-10,   5,   5,  10,  10,   5,   5, -10],
+    [-10,   0,  10,  10,  10,  10,   0, -10],
+    [-10,  10,  10,  10,  10,  10,  10, -10],
+    [-10,   5,   0,   0,   0,   0,   5, -10],
+    [-20, -10, -10, -10, -10, -10, -10, -20],
+];
+
+const ROOK_TABLE: [[i32; 8]; 8] = [
+    [  0,   0,   0,   0,   0,   0,   0,   0],
+    [  5,  10,  10,  10,  10,  10,  10,   5],
+    [ -5,   0,   0,   0,   0,   0,   0,  -5],
+    [ -5,   0,   0,   0,   0,   0,   0,  -5],
+    [ -5,   0,   0,   0,   0,   0,   0,  -5],
+    [ -5,   0,   0,   0,   0,   0,   0,  -5],
+    [ -5,   0,   0,   0,   0,   0,   0,  -5],
+    [  0,   0,   0,   5,   5,   0,   0,   0],
+];
+
+const QUEEN_TABLE: [[i32; 8]; 8] = [
+    [-20, -10, -10,  -5,  -5, -10, -10, -20],
+    [-10,   0,   0,   0,   0,   0,   0, -10],
+    [-10,   0,   5,   5,   5,   5,   0, -10],
+    [ -5,   0,   5,   5,   5,   5,   0,  -5],
+    [  0,   0,   5,   5,   5,   5,   0,  -5],
+    [-10,   5,   5,   5,   5,   5,   0, -10],
+    [-10,   0,   5,   0,   0,   0,   0, -10],
+    [-20, -10, -10,  -5,  -5, -10, -10, -20],
+];
+
+const KING_TABLE: [[i32; 8]; 8] = [
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-20, -30, -30, -40, -40, -30, -30, -20],
+    [-10, -20, -20, -20, -20, -20, -20, -10],
+    [ 20,  20,   0,   0,   0,   0,  20,  20],
+    [ 20,  30,  10,   0,   0,  10,  30,  20],
+];
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    use crate::fen;
+
+    // Deep enough for the tests, and never cut short
+    const BUDGET: Budget = Budget { depth: 2, time: None, };
+
+    fn game(fen: &str) -> Game {
+
+        Game::from_position(&fen::parse(fen).unwrap())
+    }
+
+    fn square(s: &str) -> logic::Square {
+
+        logic::Square::from_algebraic(s).unwrap()
+    }
+
+    #[test]
+    fn mates_in_one() {
+
+        let game = game("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+        let mov = best_move(&game, logic::Player::White, BUDGET).unwrap();
+
+        assert_eq!((mov.from, mov.to), (square("a1"), square("a8")));
+    }
+
+    #[test]
+    fn takes_a_hanging_piece() {
+
+        let game = game("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1");
+        let mov = best_move(&game, logic::Player::White, BUDGET).unwrap();
+
+        assert_eq!((mov.from, mov.to), (square("d2"), square("d5")));
+    }
+
+    #[test]
+    fn no_moves_no_answer() {
+
+        // Stalemate
+        let game = game("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1");
+        assert!(best_move(&game, logic::Player::Black, BUDGET).is_none());
+    }
+
+    #[test]
+    fn only_accepts_draws_when_not_ahead() {
+
+        assert!(accepts_draw(&Game::new(), logic::Player::White));
+
+        let queen_up = game("4k3/8/8/8/8/8/8/3QK3 w - - 0 1");
+        assert!(!accepts_draw(&queen_up, logic::Player::White));
+        assert!(accepts_draw(&queen_up, logic::Player::Black));
+    }
+
+    #[test]
+    fn history_is_kept_out_of_the_copies() {
+
+        let mut game = Game::new();
+        game.play(logic::Move { from: square("e2"), to: square("e4"), promotion: None, }).unwrap();
+
+        let copy = game.without_history();
+        assert!(copy.positions() == &game.positions()[1..]);
+        assert_eq!(copy.to_fen(), game.to_fen());
+    }
+}
@@ -1,129 +1,356 @@
 
-use simonsev_chess as backend;
 use chess_network_protocol::{
     self as protocol,
     ClientToServer as Cts,
     ServerToClient as Stc,
-    ClientToServerHandshake as CtsHand,
     ServerToClientHandshake as StcHand,
 };
 
-use crate::logic;
+use crate::logic::{ self, logic_to_proto, };
+use crate::fen;
+use crate::clock::{ self, Clock, TimeControl, };
+use crate::draw;
+use crate::takeback;
+use crate::extensions::Extensions;
+use crate::game::Game;
+use crate::referee::{ Announcement, Offer, Referee, Takeback, };
+use crate::door::{ Arrival, Door, };
 use crate::tcp_handler::{ self as tcp, TcpHandler, };
 
-use std::net::TcpListener;
+use std::net::{ SocketAddr, TcpListener, TcpStream, UdpSocket, };
+use std::process;
+use std::time::Duration;
 
 type ThreadResult = ();
 
-fn sqstr(x: usize, y: usize) -> String {
-
-    let a = match x {
-        0 => "A",
-        1 => "B",
-        2 => "C",
-        3 => "D",
-        4 => "E",
-        5 => "F",
-        6 => "G",
-        7 => "H",
-        _ => panic!(),
-    };
-
-    format!("{}{}", a, y + 1)
-}
-
 pub struct Server {
 
-    game: backend::Game,
-    tcp_handler: TcpHandler<Cts, Stc>,
+    referee: Referee,
+    door: Door,
+    // None until the opponent connects
+    tcp_handler: Option<TcpHandler<Cts, Stc>>,
     state: logic::State,
     player: logic::Player,
+    // State to return to after the opponent reconnects
+    resume: logic::State,
+    // What the opponent's client named in the handshake
+    extensions: Extensions,
 }
 
 impl Server {
 
-    pub fn new(port: String) -> logic::Layer {
-
-        println!("Waiting for opponent to connect...");
-        let listener = TcpListener::bind(format!("0.0.0.0:{}", port)).unwrap();
-        let (mut stream, addr) = listener.accept().unwrap();
-        println!("Connected! ({})", addr);
-
-        let game = backend::Game::new();
-
-        // Receive handshake
-        let ctshand: CtsHand = tcp::read(&mut stream);
-        println!("Client wants you to play as {:?}", ctshand.server_color);
-        let (state, player) = match ctshand.server_color {
-            protocol::Color::White => (
-                logic::State::SelectPiece,
-                logic::Player::White,
-            ),
-            protocol::Color::Black => (
-                logic::State::OpponentTurn,
-                logic::Player::Black,
-            ),
+    /// Starts from `position`, or the usual starting position if `None`
+    pub fn new(
+        addr: SocketAddr,
+        position: Option<fen::Position>,
+        control: Option<TimeControl>,
+    ) -> logic::Layer {
+
+        let listener = listen(addr);
+        let addr = shareable_addr(listener.local_addr().unwrap_or(addr));
+        // Never blocks, so the window stays up while waiting
+        let door = Door::new(listener).expect("Could not listen for opponent");
+        println!("Waiting for opponent to connect to {}...", addr);
+
+        let game = match position {
+            Some(position) => Game::from_position(&position),
+            None => Game::new(),
         };
-        
-        // Send handshake
+
+        // The clock starts once the opponent is in
+        let referee = Referee::new(game, control);
+        let state = logic::State::WaitingForOpponent { addr, };
+
+        Box::new(Self {
+            referee,
+            door,
+            tcp_handler: None,
+            state,
+            // Whatever the opponent asks for
+            player: logic::Player::White,
+            resume: state,
+            extensions: Extensions::default(),
+        })
+    }
+
+    pub fn send_handshake(
+        stream: &TcpStream,
+        game: &Game,
+        to_move: logic::Player,
+        control: Option<TimeControl>,
+    ) -> Result<(), tcp::Error> {
+
+        let mut features = vec![protocol::Features::PossibleMoveGeneration];
+        features.extend(control.map(clock::to_feature));
+        features.extend(Extensions::ours().to_features());
+
         let stchand = StcHand {
 
-            board: Self::convert_board(&game),
-            moves: Vec::new(),
+            board: game.to_protocol(),
+            moves: game.to_protocol_moves(to_move),
             joever: protocol::Joever::Ongoing,
-            features: Vec::new(),
+            features,
         };
 
-        tcp::write(&mut stream, stchand);
+        tcp::write(stream, stchand)
+    }
+
+    /// Reads a message from the opponent, handling disconnects
+    fn receive(&mut self) -> Option<Cts> {
+
+        match self.tcp_handler.as_ref()?.read() {
+            Ok(cts) => cts,
+            Err(e) => {
+                self.disconnect(e);
+                None
+            },
+        }
+    }
+
+    /// Lets in whoever connected. Spectators may come any time, players
+    /// only while we wait for the opponent to connect or come back.
+    fn answer_door(&mut self) {
+
+        match self.door.poll() {
+            Some(Arrival::Spectator { stream, addr, }) => self.referee.welcome_spectator(stream, addr),
+            // Clients name the colour they play, we get the other
+            Some(Arrival::Player { stream, addr, player, extensions, }) => match self.state {
+                logic::State::WaitingForOpponent { .. } => self.accept(stream, player.other(), extensions),
+                logic::State::Reconnecting => self.reconnected(stream, player.other(), extensions),
+                _ => println!("{} wants to play, but the game has started", addr),
+            },
+            None => (),
+        }
+    }
+
+    fn send(&mut self, stc: Stc) {
+
+        let sent = match &self.tcp_handler {
+            Some(tcp_handler) => tcp_handler.write(stc),
+            None => return,
+        };
+        if let Err(e) = sent {
+            self.disconnect(e);
+        }
+    }
+
+    /// Tells the client something the protocol has no message for
+    fn send_notice(&mut self, message: &str) {
+
+        let stc = self.referee.notice(message);
+        self.send(stc);
+    }
+
+    fn disconnect(&mut self, e: tcp::Error) {
+
+        println!("Lost connection to opponent: {}", e);
+
+        // Nothing left to play for
+        if self.state.is_over() {
+            return;
+        }
+
+        // Nobody's time runs while the opponent is away
+        self.referee.stop_clock();
+
+        self.resume = match self.state {
+            logic::State::SelectMove { .. }
+            | logic::State::SelectPromotion { .. } => logic::State::SelectPiece,
+            state => state,
+        };
+        self.state = logic::State::Disconnected { reason: e.into(), };
+    }
+
+    /// Starts the game against the first player to connect
+    fn accept(&mut self, stream: TcpStream, player: logic::Player, extensions: Extensions) {
+
+        println!("Client wants you to play as {:?}", player);
+
+        let to_move = self.referee.game().to_move();
+        if !self.greet_opponent(stream, to_move, extensions) {
+            return;
+        }
+
+        self.player = player;
+        self.state = self.turn_state();
+        self.resume = self.state;
+        self.referee.start_clock();
+    }
+
+    fn reconnected(&mut self, stream: TcpStream, player: logic::Player, extensions: Extensions) {
+
+        if player != self.player {
+            println!("Client wants you to play as {:?}, but you are {:?}", player, self.player);
+            return;
+        }
+
+        let to_move = self.referee.game().to_move();
+        if self.greet_opponent(stream, to_move, extensions) {
+            self.state = self.resume;
+            self.referee.start_clock();
+        }
+    }
+
+    /// Answers the handshake, returns whether the opponent is in
+    fn greet_opponent(
+        &mut self,
+        stream: TcpStream,
+        to_move: logic::Player,
+        extensions: Extensions,
+    ) -> bool {
+
+        let control = self.referee.clock().map(Clock::control);
+        let handshake = Self::send_handshake(&stream, self.referee.game(), to_move, control)
+            .and_then(|_| stream.set_read_timeout(None).map_err(tcp::Error::Io));
+        if let Err(e) = handshake {
+            println!("Handshake failed: {}", e);
+            return false;
+        }
         println!("Handshake complete!");
-        
-        let tcp_handler = TcpHandler::new(stream);
 
-        Box::new(Self {
-            game,
-            tcp_handler,
-            state,
-            player,
-        })
+        self.tcp_handler = Some(TcpHandler::new(stream));
+        self.extensions = extensions;
+        true
     }
 
-    fn convert_board(game: &backend::Game) -> [[protocol::Piece; 8]; 8] {
-
-        let mut target = [[protocol::Piece::None; 8]; 8];
-
-        for (backend_row, target_row) 
-            in std::iter::zip(game.get_board(), &mut target) 
-        {
-            for (backend_square, target_square) 
-                in std::iter::zip(backend_row, target_row)
-            {
-                *target_square = if backend_square.occupied {
-                    match (
-                        backend_square.piece.piece_type,
-                        backend_square.piece.white,
-                        ) {
-
-                        (backend::PieceType::Pawn,   true) => protocol::Piece::WhitePawn,
-                        (backend::PieceType::Rook,   true) => protocol::Piece::WhiteRook,
-                        (backend::PieceType::Knight, true) => protocol::Piece::WhiteKnight,
-                        (backend::PieceType::Bishop, true) => protocol::Piece::WhiteBishop,
-                        (backend::PieceType::Queen,  true) => protocol::Piece::WhiteQueen,
-                        (backend::PieceType::King,   true) => protocol::Piece::WhiteKing,
-                        
-                        (backend::PieceType::Pawn,   false) => protocol::Piece::BlackPawn,
-                        (backend::PieceType::Rook,   false) => protocol::Piece::BlackRook,
-                        (backend::PieceType::Knight, false) => protocol::Piece::BlackKnight,
-                        (backend::PieceType::Bishop, false) => protocol::Piece::BlackBishop,
-                        (backend::PieceType::Queen,  false) => protocol::Piece::BlackQueen,
-                        (backend::PieceType::King,   false) => protocol::Piece::BlackKing,
-
-                        _ => panic!(),
-                    }
-                } else { protocol::Piece::None };
-            }
+    /// Whose turn it is, as a state
+    fn turn_state(&self) -> logic::State {
+
+        if self.referee.game().to_move() == self.player {
+            logic::State::SelectPiece
+        } else {
+            logic::State::OpponentTurn
         }
+    }
 
-        target
+    fn make_move(
+        &mut self,
+        from: logic::Square,
+        dst: logic::Square,
+        promotion: Option<logic::Piece>,
+    ) {
+
+        let (start_x, start_y) = from.to_protocol();
+        let (end_x, end_y) = dst.to_protocol();
+        let promotion = match promotion {
+            Some(piece) => logic_to_proto(piece, self.player),
+            None => protocol::Piece::None,
+        };
+        let mov = protocol::Move { start_x, start_y, end_x, end_y, promotion, };
+
+        match self.referee.make_move(self.player, mov) {
+            Ok(played) => {
+                self.state = played.outcome.unwrap_or(logic::State::OpponentTurn);
+                self.send(played.announcement.message());
+            },
+            Err(_) => self.state = logic::State::SelectPiece,
+        }
+    }
+
+    fn opponent_moves(&mut self, mov: protocol::Move) {
+
+        match self.referee.make_move(self.player.other(), mov) {
+            Ok(played) => {
+                println!("Opponent played {}", played.san);
+                self.state = played.outcome.unwrap_or(logic::State::SelectPiece);
+                self.send(played.announcement.message());
+            },
+            // Opponent gets to try again
+            Err(reason) => {
+                println!("Opponent's move was refused: {}", reason);
+                self.send_notice(reason);
+            },
+        }
+    }
+
+    /// Ends the game if someone's time ran out
+    fn check_clock(&mut self) {
+
+        let (loser, announcement) = match self.referee.check_clock() {
+            Some(flagged) => flagged,
+            None => return,
+        };
+        println!("{:?} ran out of time", loser);
+
+        self.state = logic::State::OutOfTime { winner: loser.other(), };
+        self.send(announcement.message());
+    }
+
+    /// Ends the game with `winner` winning by resignation, and tells the
+    /// opponent
+    fn end_by_resignation(&mut self, winner: logic::Player) {
+
+        self.state = logic::State::Resigned { winner, };
+        let announcement = self.referee.resign(winner.other());
+        self.send(announcement.message());
+    }
+
+    /// The opponent either asks for a takeback or agrees to ours
+    fn opponent_requests_takeback(&mut self) {
+
+        match self.referee.request_takeback(self.player.other()) {
+            Takeback::Repeated => (),
+            Takeback::NothingToTakeBack => self.send_notice(takeback::DECLINED),
+            Takeback::Requested => println!("Opponent asks to take back a move"),
+            Takeback::TakenBack(announcement) => {
+                println!("Opponent agreed to take back");
+                self.taken_back(announcement);
+            },
+        }
+    }
+
+    fn taken_back(&mut self, announcement: Announcement) {
+
+        self.state = self.turn_state();
+        self.send(announcement.message());
+    }
+
+    /// The opponent either offers a draw or accepts ours
+    fn opponent_offers_draw(&mut self) {
+
+        match self.referee.offer_draw(self.player.other()) {
+            Offer::Repeated => (),
+            Offer::Made => println!("Opponent offers a draw"),
+            Offer::Accepted => {
+                println!("Opponent accepted the draw");
+                self.state = logic::State::Drawn { reason: logic::DrawReason::Agreement, };
+            },
+        }
+    }
+}
+
+/// Listens on `addr`, nothing can be hosted without it so failing exits
+pub fn listen(addr: SocketAddr) -> TcpListener {
+
+    match TcpListener::bind(addr) {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("Could not listen on {}: {}", addr, e);
+            process::exit(1);
+        },
+    }
+}
+
+/// The address others on the network reach `addr` at. Listening on every
+/// interface says nothing about that, so this asks the system which one it
+/// would reach the internet through, without sending anything.
+fn shareable_addr(addr: SocketAddr) -> SocketAddr {
+
+    if !addr.ip().is_unspecified() {
+        return addr;
+    }
+
+    let (local, remote) = match addr {
+        SocketAddr::V4(_) => ("0.0.0.0:0", "192.0.2.1:80"),
+        SocketAddr::V6(_) => ("[::]:0", "[2001:db8::1]:80"),
+    };
+    let ip = UdpSocket::bind(local)
+        .and_then(|socket| socket.connect(remote).map(|_| socket))
+        .and_then(|socket| socket.local_addr());
+
+    match ip {
+        Ok(ip) => SocketAddr::new(ip.ip(), addr.port()),
+        Err(_) => addr,
     }
 }
 
@@ -134,178 +361,279 @@ impl logic::Interface for Server {
         self.state
     }
 
-    fn get_piece_at(&self, x: u8, y: u8) -> Option<(logic::Piece, logic::Player)> {
-
-        let square = &self.game.get_board()[x as usize][y as usize];
-        if square.occupied {
-            
-            // Convert piece
-            let piece = &square.piece;
-            let piece_type = match piece.piece_type {
-                backend::PieceType::Pawn       => logic::Piece::Pawn,
-                backend::PieceType::Rook       => logic::Piece::Rook,
-                backend::PieceType::Knight     => logic::Piece::Knight,
-                backend::PieceType::Bishop     => logic::Piece::Bishop,
-                backend::PieceType::Queen      => logic::Piece::Queen,
-                backend::PieceType::King       => logic::Piece::King,
-                backend::PieceType::Unoccupied => panic!(),
-            };
-
-            let player = if piece.white {
-                logic::Player::White
-            } else {
-                logic::Player::Black
-            };
-            
-            Some((piece_type, player))
-        } else { None }
-    }
-
-    fn select_piece(&mut self, at: (u8, u8)) {
-        
-        if !matches!(self.state, logic::State::SelectPiece) {
-            return;
+    fn get_player(&self) -> Option<logic::Player> {
+
+        // Not known until the opponent picks
+        match self.state {
+            logic::State::WaitingForOpponent { .. } => None,
+            _ => Some(self.player),
         }
+    }
+
+    fn get_piece_at(&self, at: logic::Square) -> Option<(logic::Piece, logic::Player)> {
 
-        let square = &self.game.get_board()[at.0 as usize][at.1 as usize];
+        self.referee.game().piece_at(at)
+    }
 
-        if !square.occupied {
+    fn get_destinations(&self, from: logic::Square) -> Vec<logic::Square> {
+
+        self.referee.game().destinations(from)
+    }
+
+    fn select_piece(&mut self, at: logic::Square) {
+        
+        if !matches!(self.state, logic::State::SelectPiece) {
             return;
         }
 
-        if square.piece.white != matches!(self.player, logic::Player::White) {
-            return;
+        // Must be our piece
+        match self.referee.game().piece_at(at) {
+            Some((_, player)) if player == self.player => (),
+            _ => return,
         }
 
         self.state = logic::State::SelectMove { from: at, };
     }
     
     fn update(&mut self) {
+
+        if self.state.is_playing() {
+            self.check_clock();
+        }
+
+        self.answer_door();
         
         match self.state {
-            logic::State::OpponentTurn => {
-                
-                match self.tcp_handler.read() {
-                    None => (),
-                    Some(cts) => match cts {
-                        Cts::Move(mov) => {
-
-                            let piece = self.game.get_board()
-                                [mov.start_x][mov.start_y]
-                                .piece.piece_type.clone();
-
-                            use simonsev_chess::PieceType::*;
-                            let piece_str = match piece {
-                                Pawn => "pawn",
-                                Rook => "rook",
-                                Knight => "knight",
-                                Bishop => "bishop",
-                                Queen => "queen",
-                                King => "king",
-                                Unoccupied => "!!NONE!!",
-                            };
-
-                            let from = sqstr(mov.start_x, mov.start_y);
-                            let to = sqstr(mov.end_x, mov.end_y);
-                            println!("Opponent wants to move {} from {} to {}", piece_str, from, to);
-                            self.game.input_move(from, to);
-                            let valid = self.game.check_move_valid();
-                            println!("That move is {}", if valid { "legal" } else { "illegal" });
-                            self.game = self.game.clone().do_turn();
-
-                            // Move is valid if move_from is non-empty
-                            let joever = if self.game.mate {
-                                // Client check-mated server
-                                match self.player {
-                                    logic::Player::White => protocol::Joever::Black,
-                                    logic::Player::Black => protocol::Joever::White,
-                                }
-                            } else {
-                                protocol::Joever::Ongoing
-                            };
-                            
-                            if valid && matches!(mov.promotion, protocol::Piece::None) {
-
-                                let stc = Stc::State {
-                                    board: Self::convert_board(&self.game),
-                                    moves: Vec::new(),
-                                    move_made: mov,
-                                    joever,
-                                };
-
-                                self.tcp_handler.write(stc);
-                                self.state = if matches!(joever, protocol::Joever::Ongoing) {
-                                    logic::State::SelectPiece
-                                } else {
-                                    logic::State::CheckMate(self.player.other())
-                                };
-                            } else {
-
-                                let stc = Stc::Error {
-                                    board: Self::convert_board(&self.game),
-                                    moves: Vec::new(),
-                                    message: "".to_string(),
-                                    joever,
-                                };
-                                self.tcp_handler.write(stc);
-                            }
-                        },
-                        m => panic!("Unimplemented client message: {:?}", m),
-                    },
-                }
-            }
+            // The opponent may give up or offer a draw while we think
+            logic::State::OpponentTurn
+            | logic::State::SelectPiece
+            | logic::State::SelectMove { .. }
+            | logic::State::SelectPromotion { .. } => match self.receive() {
+                None => (),
+                Some(Cts::Move(mov)) if takeback::is_request(&mov) && self.extensions.takeback =>
+                    self.opponent_requests_takeback(),
+                Some(Cts::Move(mov)) => self.opponent_moves(mov),
+                Some(Cts::Resign) => {
+                    println!("Opponent resigned");
+                    self.end_by_resignation(self.player);
+                },
+                Some(Cts::Draw) => self.opponent_offers_draw(),
+            },
             _ => (),
         }
     }
 
-    fn play_move(&mut self, dst: (u8, u8)) {
+    fn resign(&mut self) {
+
+        if self.state.is_playing() {
+            println!("You resigned");
+            self.end_by_resignation(self.player.other());
+        }
+    }
+
+    /// Offers are sent as a draw message, which also accepts the
+    /// opponent's offer
+    fn offer_draw(&mut self) {
+
+        if !self.state.is_playing() {
+            return;
+        }
+
+        match self.referee.offer_draw(self.player) {
+            Offer::Repeated => return,
+            Offer::Made => println!("You offered a draw"),
+            Offer::Accepted => {
+                println!("You accepted the draw");
+                self.state = logic::State::Drawn { reason: logic::DrawReason::Agreement, };
+            },
+        }
+
+        let stc = self.referee.draw_message();
+        self.send(stc);
+    }
+
+    fn decline_draw(&mut self) {
+
+        // Other clients only see the offer lapse when they move
+        if self.referee.decline_draw(self.player) && self.extensions.draw_declined {
+            self.send_notice(draw::DECLINED);
+        }
+    }
+
+    fn get_draw_offer(&self) -> Option<logic::Player> {
+
+        self.referee.draw_offer()
+    }
+
+    fn request_takeback(&mut self) {
+
+        if !self.state.is_playing() {
+            return;
+        }
+
+        if !self.extensions.takeback {
+            println!("The opponent's client can't take back moves");
+            return;
+        }
+
+        match self.referee.request_takeback(self.player) {
+            Takeback::Repeated => (),
+            Takeback::NothingToTakeBack => println!("No move to take back"),
+            Takeback::Requested => {
+                println!("You asked to take back your move");
+                self.send_notice(takeback::REQUESTED);
+            },
+            Takeback::TakenBack(announcement) => {
+                println!("You agreed to take back");
+                self.taken_back(announcement);
+            },
+        }
+    }
+
+    fn decline_takeback(&mut self) {
+
+        if self.referee.decline_takeback(self.player) {
+            self.send_notice(takeback::DECLINED);
+        }
+    }
+
+    fn get_takeback_request(&self) -> Option<logic::Player> {
+
+        self.referee.takeback()
+    }
+
+    fn get_history(&self) -> &[String] {
+
+        self.referee.history()
+    }
+
+    fn get_fen(&self) -> String {
+
+        self.referee.game().to_fen()
+    }
+
+    fn get_start_position(&self) -> Option<fen::Position> {
+
+        Some(self.referee.game().start_position())
+    }
+
+    fn get_clock(&self, player: logic::Player) -> Option<Duration> {
+
+        self.referee.clock().map(|clock| clock.remaining(player))
+    }
+
+    fn reconnect(&mut self) {
+
+        if !matches!(self.state, logic::State::Disconnected { .. }) {
+            return;
+        }
+
+        println!("Waiting for opponent to reconnect...");
+        self.state = logic::State::Reconnecting;
+    }
+
+    fn play_move(&mut self, dst: logic::Square) {
 
         let from = match self.state {
             logic::State::SelectMove { from } => from,
             _ => return,
         };
 
-        let from_str = sqstr(from.1 as usize, from.0 as usize);
-        let to_str = sqstr(dst.1 as usize, dst.0 as usize);
-        self.game.input_move(from_str, to_str);
-        let valid = self.game.check_move_valid();
-        self.game = self.game.clone().do_turn();
-
-        if valid {
-
-            let joever = if self.game.mate {
-                match self.player {
-                    logic::Player::White => protocol::Joever::White,
-                    logic::Player::Black => protocol::Joever::Black,
-                }
-            } else {
-                protocol::Joever::Ongoing
-            };
-
-            let stc = Stc::State {
-
-                board: Self::convert_board(&self.game),
-                moves: Vec::new(),
-                joever,
-                move_made: protocol::Move {
-                    start_x: from.0 as usize,
-                    start_y: from.1 as usize,
-                    end_x: dst.0 as usize,
-                    end_y: dst.1 as usize,
-                    // Promotions not supported
-                    promotion: protocol::Piece::None,
-                }
-            };
-
-            self.state = if matches!(joever, protocol::Joever::Ongoing)
-            {
-                logic::State::OpponentTurn
-            } else {
-                logic::State::CheckMate(self.player)
-            };
-            self.tcp_handler.write(stc);
-        } else {
-            self.state = logic::State::SelectPiece;
+        // Pawns reaching the last rank need a promotion first
+        if self.referee.game().promotes(from, dst) {
+            self.state = logic::State::SelectPromotion { from, at: dst, };
+            return;
+        }
+
+        self.make_move(from, dst, None);
+    }
+
+    fn select_promotion(&mut self, piece: logic::Piece) {
+
+        let (from, at) = match self.state {
+            logic::State::SelectPromotion { from, at } => (from, at),
+            _ => return,
+        };
+
+        self.make_move(from, at, Some(piece));
+    }
+
+    fn cancel_promotion(&mut self) {
+
+        if let logic::State::SelectPromotion { from, .. } = self.state {
+            self.state = logic::State::SelectMove { from, };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::client;
+
+    use std::thread;
+    use std::time::Instant;
+
+    fn open_door() -> (Door, SocketAddr) {
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        (Door::new(listener).unwrap(), addr)
+    }
+
+    fn wait_for_arrival(door: &Door) -> Arrival {
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if let Some(arrival) = door.poll() {
+                return arrival;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        panic!("nobody arrived");
+    }
+
+    #[test]
+    fn spectators_get_the_position() {
+
+        let (door, addr) = open_door();
+        let watcher = thread::spawn(move || client::watch(&[addr], None).map(|(_, stch)| stch.board));
+
+        let (stream, addr) = match wait_for_arrival(&door) {
+            Arrival::Spectator { stream, addr, } => (stream, addr),
+            Arrival::Player { .. } => panic!("spectator taken for a player"),
+        };
+        Referee::new(Game::new(), None).welcome_spectator(stream, addr);
+
+        let board = watcher.join().unwrap().expect("spectator should get the handshake");
+        assert!(matches!(board[1][4], protocol::Piece::WhitePawn));
+    }
+
+    #[test]
+    fn players_say_their_colour() {
+
+        let (door, addr) = open_door();
+        let _player = thread::spawn(move || {
+            let stream = TcpStream::connect(addr).unwrap();
+            client::handshake(&stream, logic::Player::Black)
+        });
+
+        match wait_for_arrival(&door) {
+            Arrival::Player { player, .. } => assert_eq!(player, logic::Player::Black),
+            Arrival::Spectator { .. } => panic!("player taken for a spectator"),
         }
     }
+
+    #[test]
+    fn slow_clients_never_block() {
+
+        let (door, addr) = open_door();
+        let _silent = TcpStream::connect(addr).unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        let polled = Instant::now();
+        assert!(door.poll().is_none());
+        assert!(polled.elapsed() < Duration::from_secs(1));
+    }
 }
@@ -0,0 +1,207 @@
+use chess_network_protocol::{
+    self              as protocol,
+    ServerToClient    as Stc,
+    ClientToServer    as Cts,
+};
+
+use crate::logic::{ self, proto_to_logic, };
+use crate::fen;
+use crate::draw;
+use crate::clock;
+use crate::takeback;
+use crate::client::{ self, logic_board, protocol_san, };
+use crate::tcp_handler::TcpHandler;
+
+use std::net::SocketAddr;
+use std::process;
+use std::time::Duration;
+
+/// Follows a game on a server without taking part
+pub struct Spectator {
+
+    board:       [[protocol::Piece; 8]; 8],
+    // Legal moves of the player to move, if the server sends them
+    moves:       Vec<protocol::Move>,
+    state:       logic::State,
+    history:     Vec<String>,
+    // The board after each move, to tell why the server drew the game
+    positions:   Vec<logic::Board>,
+    // The position we joined at, the history starts there
+    start:       fen::Position,
+    tcp_handler: TcpHandler<Stc, Cts>,
+}
+
+impl Spectator {
+
+    /// `game` picks the game to watch on a lobby
+    pub fn new(addrs: &[SocketAddr], game: Option<u32>) -> logic::Layer {
+
+        println!("Connecting to address {}", addrs[0]);
+
+        let (stream, stch) = match client::watch(addrs, game) {
+            Ok(connection) => connection,
+            Err(e) => {
+                println!("Could not watch the game: {}", e);
+                process::exit(1);
+            },
+        };
+        println!("Watching!");
+
+        let board = stch.board;
+        let moves = stch.moves;
+        let to_move = client::to_move(&board, &moves);
+
+        Box::new(Self {
+            board,
+            moves,
+            state: logic::State::Watching { to_move, },
+            history: Vec::new(),
+            positions: vec![logic_board(&board)],
+            start: fen::Position::from_board(logic_board(&board), to_move, 1),
+            tcp_handler: TcpHandler::new(stream),
+        })
+    }
+
+    fn to_move(&self) -> logic::Player {
+
+        match self.state {
+            logic::State::Watching { to_move, } => to_move,
+            // Only asked while watching
+            _ => logic::Player::White,
+        }
+    }
+
+    fn moved(
+        &mut self,
+        board: [[protocol::Piece; 8]; 8],
+        moves: Vec<protocol::Move>,
+        joever: protocol::Joever,
+        move_made: protocol::Move,
+    ) {
+
+        let mover = self.to_move();
+        if let Some(san) = protocol_san(&self.board, move_made, &board, client::is_win(joever)) {
+            println!("{:?}: {}", mover, san);
+            self.history.push(san);
+            self.positions.push(logic_board(&board));
+        }
+        self.board = board;
+        self.moves = moves;
+
+        self.state = match joever {
+            protocol::Joever::Ongoing => logic::State::Watching { to_move: mover.other(), },
+            protocol::Joever::White => logic::State::CheckMate(logic::Player::White),
+            protocol::Joever::Black => logic::State::CheckMate(logic::Player::Black),
+            protocol::Joever::Draw => {
+                let reason = draw::guess_reason(
+                    &logic_board(&self.board),
+                    &self.positions,
+                    &self.history,
+                    self.moves.is_empty(),
+                );
+                logic::State::Drawn { reason, }
+            },
+            protocol::Joever::Indeterminate => logic::State::Drawn { reason: logic::DrawReason::Declared, },
+        };
+    }
+
+    /// The server rewound to `board`, whoever asked for it is to move
+    fn taken_back(&mut self, board: [[protocol::Piece; 8]; 8], moves: Vec<protocol::Move>) {
+
+        println!("Move taken back");
+        let requester = client::to_move(&board, &moves);
+        let plies = takeback::plies(requester, self.to_move());
+        self.history.truncate(self.history.len().saturating_sub(plies));
+        self.positions.truncate(self.positions.len().saturating_sub(plies).max(1));
+
+        self.board = board;
+        self.moves = moves;
+        self.state = logic::State::Watching { to_move: requester, };
+    }
+}
+
+impl logic::Interface for Spectator {
+
+    fn get_state(&self) -> logic::State {
+
+        self.state
+    }
+
+    fn get_player(&self) -> Option<logic::Player> {
+
+        None
+    }
+
+    fn update(&mut self) {
+
+        if !matches!(self.state, logic::State::Watching { .. }) {
+            return;
+        }
+
+        let stc = match self.tcp_handler.read() {
+            Ok(Some(stc)) => stc,
+            Ok(None) => return,
+            Err(e) => {
+                println!("Lost connection to server: {}", e);
+                self.state = logic::State::Disconnected { reason: e.into(), };
+                return;
+            },
+        };
+
+        match stc {
+            Stc::State { board, moves, joever, move_made, } => self.moved(board, moves, joever, move_made),
+            Stc::Resigned { joever: protocol::Joever::White, .. } =>
+                self.state = logic::State::Resigned { winner: logic::Player::White, },
+            Stc::Resigned { joever: protocol::Joever::Black, .. } =>
+                self.state = logic::State::Resigned { winner: logic::Player::Black, },
+            Stc::Draw { .. } => self.state = logic::State::Drawn { reason: logic::DrawReason::Agreement, },
+            Stc::Error { joever: protocol::Joever::White, message, .. } if message == clock::OUT_OF_TIME =>
+                self.state = logic::State::OutOfTime { winner: logic::Player::White, },
+            Stc::Error { joever: protocol::Joever::Black, message, .. } if message == clock::OUT_OF_TIME =>
+                self.state = logic::State::OutOfTime { winner: logic::Player::Black, },
+            Stc::Error { board, moves, message, .. } if message == takeback::TAKEN_BACK =>
+                self.taken_back(board, moves),
+            stc => println!("Ignoring message: {:?}", stc),
+        }
+    }
+
+    fn get_piece_at(&self, at: logic::Square) -> Option<(logic::Piece, logic::Player)> {
+
+        let (x, y) = at.to_protocol();
+        proto_to_logic(self.board[y][x])
+    }
+
+    fn get_destinations(&self, _from: logic::Square) -> Vec<logic::Square> {
+
+        Vec::new()
+    }
+
+    fn select_piece(&mut self, _at: logic::Square) {}
+
+    fn play_move(&mut self, _dst: logic::Square) {}
+
+    fn select_promotion(&mut self, _piece: logic::Piece) {}
+
+    fn get_history(&self) -> &[String] {
+
+        &self.history
+    }
+
+    fn get_fen(&self) -> String {
+
+        let fullmove = self.history.len() as u32 / 2 + 1;
+        fen::write(&fen::Position::from_board(logic_board(&self.board), self.to_move(), fullmove))
+    }
+
+    fn get_start_position(&self) -> Option<fen::Position> {
+
+        Some(self.start)
+    }
+
+    fn get_clock(&self, _player: logic::Player) -> Option<Duration> {
+
+        None
+    }
+
+    fn resign(&mut self) {}
+}
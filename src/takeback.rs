@@ -0,0 +1,38 @@
+use chess_network_protocol as protocol;
+
+use crate::logic::Player;
+
+/// The server asks for a takeback with an error carrying this message.
+/// None of these are sent to clients without the takeback extension.
+pub const REQUESTED: &str = "Takeback requested";
+
+/// The server turns down the client's request with this one
+pub const DECLINED: &str = "Takeback declined";
+
+/// And sends the board after taking back with this one
+pub const TAKEN_BACK: &str = "Move taken back";
+
+/// The client asks for a takeback, or agrees to the server's request,
+/// with a move from a square to itself, which no piece can make
+pub fn request() -> protocol::Move {
+
+    protocol::Move {
+        start_x: 0,
+        start_y: 0,
+        end_x: 0,
+        end_y: 0,
+        promotion: protocol::Piece::None,
+    }
+}
+
+pub fn is_request(mov: &protocol::Move) -> bool {
+
+    mov.start_x == mov.end_x && mov.start_y == mov.end_y
+}
+
+/// How many moves are undone to take back the last move of `requester`,
+/// two if the opponent has answered it already
+pub fn plies(requester: Player, to_move: Player) -> usize {
+
+    if to_move == requester { 2 } else { 1 }
+}
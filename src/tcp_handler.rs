@@ -1,34 +1,81 @@
 
 use std::sync::mpsc::{ self, Receiver, Sender, };
-use std::net::TcpStream;
+use std::net::{ Shutdown, TcpStream, };
 use std::thread;
+use std::time::Duration;
+use std::fmt;
 use serde::{ Serialize as Ser, de::DeserializeOwned as Des, };
+use crate::logic;
+
+// How long a write may wait for the peer to take it, a peer that
+// doesn't read would hold up the writer and dropping it forever
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
 
 pub trait Message: Ser + Des + Send + 'static {}
 impl<T: Ser + Des + Send + 'static> Message for T {}
 
-pub fn write<T: Message>(stream: &TcpStream, msg: T) {
+#[derive(Debug)]
+pub enum Error {
+    /// The other end closed the connection
+    Closed,
+    /// The connection broke
+    Io(std::io::Error),
+    /// The other end sent something we don't understand
+    Json(serde_json::Error),
+}
+
+impl From<serde_json::Error> for Error {
+
+    fn from(e: serde_json::Error) -> Self {
+
+        if e.is_eof() {
+            Error::Closed
+        } else if e.is_io() {
+            Error::Io(e.into())
+        } else {
+            Error::Json(e)
+        }
+    }
+}
+
+impl fmt::Display for Error {
 
-    serde_json::to_writer(stream, &msg).unwrap();
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+
+        match self {
+            Error::Closed  => write!(f, "connection closed"),
+            Error::Io(e)   => write!(f, "connection error: {}", e),
+            Error::Json(e) => write!(f, "invalid message: {}", e),
+        }
+    }
 }
 
-pub fn read<T: Message>(stream: &TcpStream) -> T {
+pub fn write<T: Message>(stream: &TcpStream, msg: T) -> Result<(), Error> {
+
+    serde_json::to_writer(stream, &msg)?;
+    Ok(())
+}
+
+pub fn read<T: Message>(stream: &TcpStream) -> Result<T, Error> {
 
     let mut de = serde_json::Deserializer::from_reader(stream);
-    T::deserialize(&mut de).unwrap()
+    Ok(T::deserialize(&mut de)?)
 }
 
 type ThreadResult = ();
 
-pub struct TcpHandler<R, W> 
+pub struct TcpHandler<R, W>
     where R: Message,
           W: Message
 {
 
-    receiver:     Receiver<R>,
-    sender:       Sender<W>,
-    read_handle:  thread::JoinHandle<ThreadResult>,
-    write_handle: thread::JoinHandle<ThreadResult>,
+    receiver:     Receiver<Result<R, Error>>,
+    // Only taken when dropping, to stop the write thread
+    sender:       Option<Sender<W>>,
+    // Kept to unblock the read thread when dropping
+    stream:       TcpStream,
+    read_handle:  Option<thread::JoinHandle<ThreadResult>>,
+    write_handle: Option<thread::JoinHandle<ThreadResult>>,
 }
 
 impl<R, W> TcpHandler<R, W>
@@ -41,67 +88,143 @@ impl<R, W> TcpHandler<R, W>
         let (read_sender, receiver)  = mpsc::channel();
         let (sender, write_receiver) = mpsc::channel();
 
+        // Timing out breaks the connection like any other write error
+        let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+
         let stream2 = stream.try_clone().unwrap();
+        let stream3 = stream.try_clone().unwrap();
+        // The writer reports its errors through the read channel
+        let error_sender = read_sender.clone();
 
         let read_handle = thread::spawn(move ||
-            Self::read_loop(stream, read_sender)
+            Self::read_loop(stream2, read_sender)
         );
 
         let write_handle = thread::spawn(move ||
-            Self::write_loop(stream2, write_receiver)
+            Self::write_loop(stream3, write_receiver, error_sender)
         );
 
         Self {
 
             receiver,
-            sender,
-            read_handle,
-            write_handle,
+            sender: Some(sender),
+            stream,
+            read_handle: Some(read_handle),
+            write_handle: Some(write_handle),
         }
     }
 
-    pub fn read(&self) -> Option<R> {
+    /// Returns the next message if there is one, or the reason the
+    /// connection was lost
+    pub fn read(&self) -> Result<Option<R>, Error> {
 
         match self.receiver.try_recv() {
-            Ok(msg) => Some(msg),
+            Ok(Ok(msg)) => Ok(Some(msg)),
+            Ok(Err(e)) => Err(e),
             Err(e) => match e {
-                mpsc::TryRecvError::Empty => None,
-                mpsc::TryRecvError::Disconnected => panic!(),
+                mpsc::TryRecvError::Empty => Ok(None),
+                mpsc::TryRecvError::Disconnected => Err(Error::Closed),
             },
         }
     }
 
-    pub fn write(&self, msg: W) {
+    pub fn write(&self, msg: W) -> Result<(), Error> {
 
-        self.sender.send(msg).unwrap();
+        // Only fails if the write thread has exited
+        match &self.sender {
+            Some(sender) => sender.send(msg).map_err(|_| Error::Closed),
+            None => Err(Error::Closed),
+        }
     }
 
     fn read_loop(
         stream: TcpStream,
-        sender: Sender<R>,
+        sender: Sender<Result<R, Error>>,
     ) -> ThreadResult {
 
         loop {
 
             let msg = read(&stream);
-            sender.send(msg).unwrap();
+            let done = msg.is_err();
+
+            if sender.send(msg).is_err() || done {
+                return;
+            }
         }
     }
 
     fn write_loop(
         stream: TcpStream,
         receiver: Receiver<W>,
+        error_sender: Sender<Result<R, Error>>,
     ) -> ThreadResult {
 
-        loop {
+        // Ends when the handler drops its sender
+        for msg in receiver {
 
-            match receiver.try_recv() {
-                Ok(msg) => write(&stream, msg),
-                Err(e) => match e {
-                    mpsc::TryRecvError::Empty => (),
-                    mpsc::TryRecvError::Disconnected => return (),
-                }
+            if let Err(e) = write(&stream, msg) {
+                // Wake up the reader too
+                let _ = stream.shutdown(Shutdown::Both);
+                let _ = error_sender.send(Err(e));
+                return;
             }
         }
     }
 }
+
+impl<R, W> Drop for TcpHandler<R, W>
+    where R: Message,
+          W: Message
+{
+
+    fn drop(&mut self) {
+
+        // The reader is blocked until the stream closes, the writer
+        // flushes what's queued and ends with the sender
+        let _ = self.stream.shutdown(Shutdown::Read);
+        self.sender = None;
+        for handle in [self.write_handle.take(), self.read_handle.take()].into_iter().flatten() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl From<Error> for logic::Disconnect {
+
+    fn from(e: Error) -> Self {
+
+        match e {
+            Error::Closed  => logic::Disconnect::Closed,
+            Error::Io(_)   => logic::Disconnect::Broken,
+            Error::Json(_) => logic::Disconnect::Invalid,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    use std::net::TcpListener;
+    use std::time::Instant;
+
+    #[test]
+    fn dropping_never_hangs_on_a_peer_that_doesnt_read() {
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (_peer, _) = listener.accept().unwrap();
+
+        let handler: TcpHandler<String, String> = TcpHandler::new(stream);
+        for _ in 0..64 {
+            handler.write("x".repeat(1 << 20)).unwrap();
+        }
+
+        let dropped = Instant::now();
+        drop(handler);
+        // The kernel still takes a little now and then, so the writer may
+        // stall more than once before a write times out
+        assert!(dropped.elapsed() < WRITE_TIMEOUT * 10);
+    }
+}
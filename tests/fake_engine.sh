@@ -0,0 +1,24 @@
+#!/bin/sh
+# A scripted UCI engine for the tests. Every command is echoed back as an
+# info string and searches are answered with the Reply option, e7e5 unless
+# set. A reply of "exit" makes it quit instead of answering.
+
+reply=e7e5
+
+while read -r line; do
+    echo "info string $line"
+    case "$line" in
+        uci)
+            echo "id name Fake"
+            echo "uciok" ;;
+        isready)
+            echo "readyok" ;;
+        "setoption name Reply value "*)
+            reply=${line#setoption name Reply value } ;;
+        go*)
+            [ "$reply" = exit ] && exit 0
+            echo "bestmove $reply" ;;
+        quit)
+            exit 0 ;;
+    esac
+done